@@ -0,0 +1,128 @@
+// Times the compute step of compute.wgsl and compute2.wgsl for growing flocks and writes CSV:
+//     cargo bench --bench compute -- [--steps=<steps>] [output.csv]
+// The steps are timed on the GPU with timestamp queries when the adapter has them, otherwise
+// on the wall clock, submitting the steps one by one and waiting for each of them.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+use anyhow::Context;
+use boids_web::{BoidSimulation, GpuTimer, SimulationParams};
+
+const BOID_COUNTS: [u32; 3] = [1_000, 10_000, 100_000];
+const SHADERS: [(&str, &str); 2] = [
+    ("compute.wgsl", include_str!("../src/compute.wgsl")),
+    ("compute2.wgsl", include_str!("../src/compute2.wgsl")),
+];
+const WARMUP_STEPS: u32 = 10;
+const DEFAULT_STEPS: u32 = 100;
+// Steps timed per command buffer
+const BATCH_STEPS: u32 = 10;
+const DELTA_TIME: f32 = 1.0 / 60.0;
+
+// A headless device with the timestamp queries when the adapter has them
+fn device() -> anyhow::Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::Backends::all());
+    let adapter = [false, true].iter().find_map(|&force_fallback_adapter| {
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter,
+        }))
+    }).context("no GPU adapter available")?;
+    let (device, queue) = pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+            limits: wgpu::Limits::default(),
+            label: None,
+        },
+        None,
+    )).with_context(|| format!("could not open {}", adapter.get_info().name))?;
+    Ok((adapter, device, queue))
+}
+
+// Duration of every step in milliseconds, on the GPU clock. The steps are submitted in small
+// batches so that no command buffer runs long enough to trip the driver's timeout
+fn time_on_gpu(device: &wgpu::Device, queue: &wgpu::Queue, simulation: &mut BoidSimulation, timer: &GpuTimer) -> anyhow::Result<Vec<f64>> {
+    let steps: Vec<u32> = (0..timer.span_count()).collect();
+    for batch in steps.chunks(BATCH_STEPS as usize) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        for &step in batch {
+            timer.start(&mut encoder, step);
+            simulation.step(queue, &mut encoder, DELTA_TIME);
+            timer.stop(&mut encoder, step);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        device.poll(wgpu::Maintain::Wait);
+    }
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    timer.resolve(&mut encoder);
+    queue.submit(std::iter::once(encoder.finish()));
+    timer.read(device)
+}
+
+// Duration of every step in milliseconds, on the wall clock, including the submission
+fn time_on_cpu(device: &wgpu::Device, queue: &wgpu::Queue, simulation: &mut BoidSimulation, steps: u32) -> Vec<f64> {
+    (0..steps).map(|_| {
+        let start = Instant::now();
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        simulation.step(queue, &mut encoder, DELTA_TIME);
+        queue.submit(std::iter::once(encoder.finish()));
+        device.poll(wgpu::Maintain::Wait);
+        start.elapsed().as_secs_f64() * 1000.0
+    }).collect()
+}
+
+fn main() -> anyhow::Result<()> {
+    // cargo bench passes --bench along with the arguments following --
+    let steps = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--steps=").map(|steps| steps.parse::<u32>()))
+        .transpose()
+        .context("--steps must be a positive integer")?
+        .unwrap_or(DEFAULT_STEPS)
+        .clamp(1, wgpu::QUERY_SET_MAX_QUERIES / 2);
+    let mut output: Box<dyn Write> = match std::env::args().skip(1).find(|arg| !arg.starts_with("--")) {
+        Some(path) => Box::new(BufWriter::new(File::create(&path).with_context(|| format!("could not create {}", path))?)),
+        None => Box::new(std::io::stdout()),
+    };
+
+    let (adapter, device, queue) = match device() {
+        Ok(device) => device,
+        Err(e) => {
+            eprintln!("{:?}, skipping", e);
+            return Ok(());
+        }
+    };
+    let info = adapter.get_info();
+    let timer = GpuTimer::new(&device, &queue, steps);
+    eprintln!(
+        "Running on {} ({:?}, {:?}), timing {} steps on the {}",
+        info.name, info.device_type, info.backend, steps,
+        if timer.is_some() { "GPU clock" } else { "wall clock" },
+    );
+
+    writeln!(output, "shader,boids,clock,steps,mean_ms,median_ms,p95_ms,min_ms,max_ms")?;
+    for (name, source) in SHADERS {
+        for boid_count in BOID_COUNTS {
+            let mut simulation = BoidSimulation::with_shader(&device, &queue, SimulationParams::default(), boid_count, 1.0, 0, source);
+            time_on_cpu(&device, &queue, &mut simulation, WARMUP_STEPS);
+            let (clock, mut durations) = match &timer {
+                Some(timer) => ("gpu", time_on_gpu(&device, &queue, &mut simulation, timer)?),
+                None => ("wall", time_on_cpu(&device, &queue, &mut simulation, steps)),
+            };
+
+            durations.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            let percentile = |p: f64| durations[((durations.len() - 1) as f64 * p).round() as usize];
+            let mean = durations.iter().sum::<f64>() / durations.len() as f64;
+            writeln!(
+                output,
+                "{},{},{},{},{:.4},{:.4},{:.4},{:.4},{:.4}",
+                name, boid_count, clock, durations.len(), mean, percentile(0.5), percentile(0.95),
+                durations[0], durations[durations.len() - 1],
+            )?;
+            output.flush()?;
+            eprintln!("{} with {} boids: {:.3} ms per step", name, boid_count, mean);
+        }
+    }
+    Ok(())
+}
@@ -1,466 +1,1051 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
-use wgpu::{Surface, Device, Queue, SurfaceConfiguration, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, PipelineLayoutDescriptor, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferSize, BindGroupDescriptor, PipelineLayout, ComputePipeline, ComputePassDescriptor, VertexAttribute};
+use anyhow::Context;
+use wgpu::{Surface, Device, Queue, SurfaceConfiguration};
 use winit::window::Window;
-use winit::event::{WindowEvent, KeyboardInput, VirtualKeyCode};
-use crate::boid::Boid;
-use wgpu::util::{BufferInitDescriptor, DeviceExt};
-use bytemuck::{Contiguous, Zeroable, Pod};
-use crate::camera::{Camera, CameraUniform, CameraController};
-use arr_macro::arr;
+use winit::event::{WindowEvent, KeyboardInput, VirtualKeyCode, ElementState};
+use crate::camera::CameraController;
+use crate::flow_field::FlowField;
+use crate::mapping::Readback;
+use crate::metrics::{FlockMetrics, MetricsLog, MetricsParams};
+use crate::mouse::MouseController;
+use crate::profiler::GpuTimer;
+use crate::recorder::{Recorder, RecordingParams};
+use crate::renderer::BoidRenderer;
+use crate::shape::BoidShape;
+use crate::simulation::BoidSimulation;
+use crate::stats::Stats;
+use crate::trajectory::{TrajectoryParams, TrajectoryRecorder};
+use crate::waypoints::WaypointPath;
+
+/// Must match MAX_NEIGHBOURS in the compute shaders
+pub const MAX_NEIGHBOURS: u32 = 16;
+/// Must match DEBUG_VECTORS in the compute and draw shaders
+pub const DEBUG_VECTORS: u32 = 5;
+
+/// How the neighbours of a boid are selected :
+/// Metric => every boid within the rule's reach
+/// Topological => the k nearest boids, k being set per rule
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Neighbourhood {
+    /// Every boid within the reach of the rule
+    Metric,
+    /// The k nearest boids, k being set per rule
+    Topological,
+}
+
+impl Neighbourhood {
+    fn toggled(self) -> Self {
+        match self {
+            Neighbourhood::Metric => Neighbourhood::Topological,
+            Neighbourhood::Topological => Neighbourhood::Metric,
+        }
+    }
+}
+
+/// 2D boids with a panning camera or 3D boids with an orbiting perspective camera
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dimensions {
+    /// Boids on a plane, seen from above
+    Two,
+    /// Boids in space, seen through the orbit camera
+    Three,
+}
+
+/// Parameters of the flocking rules, shared by the 2D and the 3D simulations
 #[derive(Clone, Debug)]
 pub struct SimulationParams{
-    pub(crate) separation_reach: f32,
-    pub(crate) separation_scale: f32,
-    pub(crate) alignement_reach: f32,
-    pub(crate) alignement_scale: f32,
-    pub(crate) cohesion_reach: f32,
-    pub(crate) cohesion_scale: f32,
-    pub(crate) color_mult: f32,
-    pub(crate) step_mult:f32,
-    pub(crate) center_attraction: f32
+    /// Whether the boids fly in 2D or in 3D, fixed once the simulation is created
+    pub dimensions: Dimensions,
+    /// Distance within which the boids move away from each other
+    pub separation_reach: f32,
+    /// Weight of the separation steering
+    pub separation_scale: f32,
+    /// Distance within which the boids match the velocity of each other
+    pub alignement_reach: f32,
+    /// Weight of the alignement steering
+    pub alignement_scale: f32,
+    /// Distance within which the boids head to the center of their neighbours
+    pub cohesion_reach: f32,
+    /// Weight of the cohesion steering
+    pub cohesion_scale: f32,
+    /// How much more the boids follow neighbours of a similar color
+    pub color_mult: f32,
+    /// Scales the simulated time, which runs twice as fast as the real time at 1
+    pub step_mult:f32,
+    /// Pull toward the origin, growing steeply past a distance of 20
+    pub center_attraction: f32,
+    /// Whether the rules consider the boids within their reach or the nearest ones
+    pub neighbourhood: Neighbourhood,
+    /// Nearest boids each rule considers with the topological neighbourhood, capped at
+    /// MAX_NEIGHBOURS by the compute shaders
+    pub separation_k: u32,
+    /// Nearest boids the alignement considers with the topological neighbourhood
+    pub alignement_k: u32,
+    /// Nearest boids the cohesion considers with the topological neighbourhood
+    pub cohesion_k: u32,
+    /// The speed of the boids is clamped between min_speed and max_speed
+    pub min_speed: f32,
+    /// Speed of the boids heading to a waypoint, and highest speed of all of them
+    pub max_speed: f32,
+    /// Divides the steering, higher values making the boids turn more slowly
+    pub inertia: f32,
+    /// Norm the sum of the steering forces is clamped to
+    pub max_steering_force: f32,
+    /// Field drifting the boids, only in 2D
+    pub flow_field: FlowField,
+    /// Weight of the flow field drift
+    pub flow_scale: f32,
+    /// Number of species the boids are spread over, in turn
+    pub species_count: u32,
+    /// Waypoints the boids of some or all of the species follow, only in 2D
+    pub paths: Vec<WaypointPath>,
+    /// Whether the boids follow the paths, toggled at runtime
+    pub follow_paths: bool,
+    /// Weight of the steering toward the current waypoint
+    pub goal_scale: f32,
+    /// Force with which the mouse attracts or repels the boids
+    pub mouse_strength: f32,
+    /// Distance within which the mouse acts, its force fading out toward it
+    pub mouse_radius: f32,
+}
+
+/// The flock of the application in 2D, without flow field nor paths
+impl Default for SimulationParams {
+    fn default() -> Self {
+        Self {
+            dimensions: Dimensions::Two,
+            separation_reach: 4.0,
+            separation_scale: 1.0,
+            alignement_reach: 1.0,
+            alignement_scale: 7.5,
+            cohesion_reach: 4.0,
+            cohesion_scale: 3.0,
+            color_mult: 5.0,
+            step_mult: 1.0,
+            center_attraction: 6.0,
+            neighbourhood: Neighbourhood::Metric,
+            separation_k: 4,
+            alignement_k: 7,
+            cohesion_k: 7,
+            min_speed: 0.5,
+            max_speed: 1.0,
+            inertia: 20.0,
+            max_steering_force: 40.0,
+            flow_field: FlowField::none(),
+            flow_scale: 5.0,
+            species_count: 2,
+            paths: vec![],
+            follow_paths: false,
+            goal_scale: 10.0,
+            mouse_strength: 60.0,
+            mouse_radius: 8.0,
+        }
+    }
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, Pod, Zeroable)]
-pub struct SimuUniforms {
-    delta_time: f32,
-    separation_reach: f32,
-    separation_scale: f32,
-    alignement_reach: f32,
-    alignement_scale: f32,
-    cohesion_reach: f32,
-    cohesion_scale: f32,
-    color_mult: f32,
-    center_attraction: f32,
+/// What the boid colors show
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorMode {
+    /// The colors of the boids, blending into each other as they flock
+    Stored,
+    /// The speed through the colormap
+    Speed,
+    /// The heading around the hue wheel
+    Heading,
+    /// The neighbour count through the colormap
+    Density,
+    /// A color per species
+    Species,
 }
 
-impl SimulationParams{
-    fn create_uniforms(&self, delta_time: f32)-> SimuUniforms{
-        SimuUniforms{
-            delta_time,
-            separation_reach: self.separation_reach,
-            separation_scale: self.separation_scale,
-            alignement_reach: self.alignement_reach,
-            alignement_scale: self.alignement_scale,
-            cohesion_reach: self.cohesion_reach,
-            cohesion_scale: self.cohesion_scale,
-            color_mult: self.color_mult,
-            center_attraction: self.center_attraction
+impl ColorMode {
+    fn next(self) -> Self {
+        match self {
+            ColorMode::Stored => ColorMode::Speed,
+            ColorMode::Speed => ColorMode::Heading,
+            ColorMode::Heading => ColorMode::Density,
+            ColorMode::Density => ColorMode::Species,
+            ColorMode::Species => ColorMode::Stored,
         }
     }
 }
 
-impl SimuUniforms{
-    fn update(&mut self, delta_time: f32){
-        self.delta_time = delta_time;
+/// Colormaps used by every color mode but the stored color and the heading
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Colormap {
+    /// Perceptually uniform, from blue to yellow
+    Viridis,
+    /// Perceptually uniform, from black to light yellow
+    Magma,
+    /// Around the hue wheel
+    Hsv,
+}
+
+impl Colormap {
+    fn next(self) -> Self {
+        match self {
+            Colormap::Viridis => Colormap::Magma,
+            Colormap::Magma => Colormap::Hsv,
+            Colormap::Hsv => Colormap::Viridis,
+        }
     }
 }
 
-const BOID_VERTICES: &[[f32; 2]] = &[
-    [0.0, 0.1],
-    [-0.045, -0.1],
-    [0.0, -0.065],
-    [0.045, -0.1],
-];
+/// Density heatmap drawn behind the boids or in their place
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HeatmapMode {
+    /// Only the boids
+    Off,
+    /// The heatmap under the boids
+    Behind,
+    /// The heatmap without the boids
+    Instead,
+}
+
+impl HeatmapMode {
+    fn next(self) -> Self {
+        match self {
+            HeatmapMode::Off => HeatmapMode::Behind,
+            HeatmapMode::Behind => HeatmapMode::Instead,
+            HeatmapMode::Instead => HeatmapMode::Off,
+        }
+    }
+}
 
-// const BOID_VERTICES: &[f32] = &[-0.01f32, -0.02, 0.01, -0.02, 0.00, 0.02];
+/// Perception radii and steering vectors of some boids or of the whole flock
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DebugView {
+    /// No debug overlay
+    Off,
+    /// The boids of RenderParams::debug_selection
+    Selected,
+    /// Every boid
+    All,
+}
 
-const BOID_TRIANGLE: &[u16] = &[
-    0,1,2,
-    2,3,0
-];
+impl DebugView {
+    fn next(self) -> Self {
+        match self {
+            DebugView::Off => DebugView::Selected,
+            DebugView::Selected => DebugView::All,
+            DebugView::All => DebugView::Off,
+        }
+    }
+}
 
+/// How the flock is drawn and which overlays are available
+#[derive(Clone, Debug)]
+pub struct RenderParams{
+    /// What the colors of the boids show
+    pub color_mode: ColorMode,
+    /// Colormap of the speed, density and heatmap
+    pub colormap: Colormap,
+    /// Neighbour count mapped to the end of the colormap in the density mode
+    pub max_density: f32,
+    /// Whether the past positions of the boids are drawn behind them
+    pub trails: bool,
+    /// Number of positions kept per boid
+    pub trail_length: u32,
+    /// Exponent of the trail fading, higher values giving shorter looking trails
+    pub trail_decay: f32,
+    /// Samples per pixel, 1 disabling MSAA or 4
+    pub msaa_samples: u32,
+    /// Blends the boids using their alpha instead of drawing them opaque
+    pub alpha_blending: bool,
+    /// Initial alpha of every boid
+    pub boid_alpha: f32,
+    /// Shape of each species in 2D, cycled through when there are more species than shapes
+    pub shapes: Vec<BoidShape>,
+    /// Length of the boids in world units
+    pub boid_size: f32,
+    /// Whether the density heatmap is drawn
+    pub heatmap: HeatmapMode,
+    /// Cells per side of the density grid
+    pub heatmap_resolution: u32,
+    /// Half the side of the square covered by the density grid
+    pub heatmap_extent: f32,
+    /// Boids per cell mapped to the end of the colormap
+    pub heatmap_max: f32,
+    /// Opacity of the heatmap drawn behind the boids
+    pub heatmap_opacity: f32,
+    /// Boids whose perception radii and steering vectors are drawn
+    pub debug: DebugView,
+    /// Indices of the boids shown by DebugView::Selected
+    pub debug_selection: Vec<u32>,
+    /// World units per unit of steering force
+    pub debug_vector_scale: f32,
+    /// Grid, axes, world boundary and scale bar, only in 2D
+    pub background: bool,
+    /// Half the side of the world boundary outline
+    pub world_half_size: f32,
+}
 
+/// What the application records and logs besides drawing the flock
+#[derive(Clone, Debug)]
+pub struct AppParams {
+    /// Video recording started and stopped with the R key
+    pub recording: RecordingParams,
+    /// Time between two statistics written to the log, zero to disable it
+    pub stats_log_interval: Duration,
+    /// Collective motion metrics sampled from the flock and written to a CSV file
+    pub metrics: MetricsParams,
+    /// Positions, velocities and colors of the boids written to a file at an interval
+    pub trajectory: TrajectoryParams,
+}
+
+/// Number of boids simulated by the application
+const BOID_COUNT: u32 = 1000;
+
+// The boids are copied back to the CPU at this interval to survive a device loss
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(2);
+
+async fn request_adapter(instance: &wgpu::Instance, surface: &Surface, force_fallback_adapter: bool) -> Option<wgpu::Adapter> {
+    instance.request_adapter(
+        &wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: Some(surface),
+            force_fallback_adapter,
+        },
+    ).await
+}
+
+async fn open_device(adapter: &wgpu::Adapter) -> anyhow::Result<(Device, Queue)> {
+    let info = adapter.get_info();
+    log::info!("Running on {} ({:?}, {:?})", info.name, info.device_type, info.backend);
+    adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            // Only used to profile the passes when the adapter has them
+            features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+            limits: wgpu::Limits::default(),
+            label: None,
+        },
+        None, // Trace path
+    ).await.with_context(|| format!("could not open {}, the device may have been lost or lack the required limits", info.name))
+}
+
+// A device able to draw to the surface, from a software adapter when no hardware one is
+// compatible or when it can't be opened
+async fn request_device(instance: &wgpu::Instance, surface: &Surface) -> anyhow::Result<(wgpu::Adapter, Device, Queue)> {
+    match request_adapter(instance, surface, false).await {
+        Some(adapter) => match open_device(&adapter).await {
+            Ok((device, queue)) => return Ok((adapter, device, queue)),
+            Err(e) => log::warn!("{:?}, falling back to a software adapter", e),
+        },
+        None => log::warn!("No GPU adapter found, falling back to a software one"),
+    }
+    let adapter = request_adapter(instance, surface, true).await
+        .context("no GPU adapter compatible with the window, not even a software one")?;
+    let (device, queue) = open_device(&adapter).await?;
+    Ok((adapter, device, queue))
+}
+
+// Spans of the GPU timer
+const COMPUTE_SPAN: u32 = 0;
+const RENDER_SPAN: u32 = 1;
+
+fn create_gpu_timer(device: &Device, queue: &Queue) -> Option<GpuTimer> {
+    let timer = GpuTimer::new(device, queue, 2);
+    if timer.is_none() {
+        log::info!("No timestamp queries on this device, the passes won't be profiled");
+    }
+    timer
+}
+
+// The flow buffer is sized for the largest field so that any preset can be swapped in
+fn flow_capacity(flow_presets: &[FlowField]) -> usize {
+    flow_presets.iter().map(|f| f.cells.len()).max().unwrap_or(1)
+}
+
+// Logs the errors no error scope caught instead of panicking, flagging the device as lost when
+// it ran out of memory. The other losses show up as wgpu panics or readbacks failing to map, see
+// guard_device_loss and check_device_loss
+fn watch_errors(device: &Device, device_lost: Arc<AtomicBool>) {
+    device.on_uncaptured_error(move |error| {
+        log::error!("{}", error);
+        if let wgpu::Error::OutOfMemory { .. } = error {
+            device_lost.store(true, Ordering::SeqCst);
+        }
+    });
+}
+
+// Causes of the wgpu panics meaning the device is gone. wgpu 0.12 has no device lost callback
+// and panics with "Error in <call>: <cause>" when a submit or a poll fails, instead of
+// reporting the error
+const DEVICE_LOSS_CAUSES: [&str; 2] = ["parent device is lost", "GPU got stuck :("];
+
+fn is_device_loss_panic(payload: &(dyn Any + Send)) -> bool {
+    let message = match (payload.downcast_ref::<String>(), payload.downcast_ref::<&str>()) {
+        (Some(message), _) => message.as_str(),
+        (None, Some(message)) => message,
+        (None, None) => return false,
+    };
+    message.starts_with("Error in ") && DEVICE_LOSS_CAUSES.iter().any(|cause| message.ends_with(cause))
+}
+
+fn create_boid_readback<T>(device: &Device, simulation: &BoidSimulation, label: &str) -> Readback<T> {
+    Readback::new(device, label, simulation.boids_size())
+}
+
+/// Runs a simulation and draws it to the surface of a window, handling the keyboard and mouse
 pub struct ApplicationState{
     // WGPU related fields
+    instance: wgpu::Instance,
     surface: Surface,
     device: Device,
     queue: Queue,
+    device_lost: Arc<AtomicBool>,
     config: SurfaceConfiguration,
-    pub(crate) size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline: RenderPipeline,
-    compute_pipeline: ComputePipeline,
-    camera:Camera,
-    camera_uniform:CameraUniform,
-    camera_bind_group:wgpu::BindGroup,
-    boid_bind_groups:Vec<wgpu::BindGroup>,
-    simu_uniform:SimuUniforms,
-    workgroup_count:u32,
-
-    //Buffers
-    boid_vertex_buffer: wgpu::Buffer,
-    boid_triangle_buffer: wgpu::Buffer,
-    boid_buffers: Vec<wgpu::Buffer>,
-    camera_buffer:wgpu::Buffer,
-    params_buffer:wgpu::Buffer,
+    size: winit::dpi::PhysicalSize<u32>,
 
+    simulation: BoidSimulation,
+    renderer: BoidRenderer,
+    recorder: Option<Recorder>,
+    stats: Stats,
+    gpu_timer: Option<GpuTimer>,
+    metrics: Option<MetricsLog>,
+    // Boids copied at the step and sim time of a metrics sample
+    metrics_readback: Readback<(u32, f32)>,
+    trajectory: Option<TrajectoryRecorder>,
+    app_params: AppParams,
+    // Whether the compute pass of the frame being drawn is timed
+    profiling_frame: bool,
 
     // Application Related fields
-    simulation_params: SimulationParams,
-    boid_count: u32,
-    camera_controller:CameraController,
-    previous_update:Instant,
-    frame:u32,
-
+    camera_controller: CameraController,
+    mouse_controller: MouseController,
+    flow_presets: Vec<FlowField>,
+    flow_preset: usize,
+    previous_update: Instant,
+    // Latest boids read back from the GPU, restored when the device is recreated
+    snapshot: Option<Vec<u8>>,
+    snapshot_readback: Readback<()>,
+    previous_snapshot: Instant,
 }
 
 impl ApplicationState{
-    pub async fn init(window:&Window, simulation_params :SimulationParams)->Self{
+    /// Creates the surface of the window, the GPU resources and a random flock, falling back to
+    /// a software adapter when no hardware one is compatible with the window
+    pub async fn init(window:&Window, simulation_params :SimulationParams, render_params: RenderParams, app_params: AppParams)->anyhow::Result<Self>{
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
         // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::Backends::all());
         let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance.request_adapter(
-            &wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            },
-        ).await.unwrap();
-
-        let (device, queue) = adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                features: wgpu::Features::empty(),
-                limits: wgpu::Limits::default(),
-                label: None,
-            },
-            None, // Trace path
-        ).await.unwrap();
+        let (adapter, device, queue) = request_device(&instance, &surface).await?;
+        let device_lost = Arc::new(AtomicBool::new(false));
+        watch_errors(&device, device_lost.clone());
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_preferred_format(&adapter).unwrap(),
+            format: surface.get_preferred_format(&adapter).context("the window surface is not supported by the adapter")?,
             width: size.width,
             height: size.height,
             present_mode: wgpu::PresentMode::Fifo,
         };
         surface.configure(&device, &config);
 
-        let shader = device.create_shader_module(&ShaderModuleDescriptor{
-            label: Some("RenderBoids"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("draw.wgsl").into())
-        });
+        // The configured field followed by generated ones covering the same area
+        let flow_field = &simulation_params.flow_field;
+        let area = || FlowField::new([-30.0, -30.0], 1.0, 61, 61);
+        let flow_presets = vec![
+            flow_field.clone(),
+            area().uniform([1.0, 0.0]),
+            area().vortex([0.0, 0.0], 15.0),
+            area().curl_noise(0.05, 42),
+        ];
+        let flow_capacity = flow_capacity(&flow_presets);
 
-        let camera_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor{
-            label: Some("CameraBindGroup"),
-            entries: &[
-                BindGroupLayoutEntry{
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: BufferSize::new(std::mem::size_of::<CameraUniform>() as u64)
-                    },
-                    count: None
-                }
-            ]
-        });
+        let stats = Stats::new(BOID_COUNT, app_params.stats_log_interval);
+        let simulation = BoidSimulation::new(&device, &queue, simulation_params, BOID_COUNT, render_params.boid_alpha, flow_capacity);
+        let renderer = BoidRenderer::new(&device, &queue, config.format, size, &simulation, render_params);
+        let gpu_timer = create_gpu_timer(&device, &queue);
+        let snapshot_readback = create_boid_readback(&device, &simulation, "Snapshot readback buffer");
+        let metrics_readback = create_boid_readback(&device, &simulation, "Metrics readback buffer");
+        let metrics = match &app_params.metrics.output {
+            Some(path) => Some(MetricsLog::create(path.clone())?),
+            None => None,
+        };
+        let trajectory_params = &app_params.trajectory;
+        let trajectory = match &trajectory_params.output {
+            Some(path) => Some(TrajectoryRecorder::start(&device, &simulation, path.clone(), trajectory_params.format)?),
+            None => None,
+        };
 
-        let camera = Camera::new();
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera, size);
-        dbg!(camera_uniform);
+        Ok(Self {
+            instance,
+            surface,
+            device,
+            queue,
+            device_lost,
+            config,
+            size,
+            simulation,
+            renderer,
+            recorder: None,
+            stats,
+            gpu_timer,
+            metrics,
+            metrics_readback,
+            trajectory,
+            app_params,
+            profiling_frame: false,
+            camera_controller: CameraController::new(1., 0.05),
+            mouse_controller: MouseController::new(),
+            flow_presets,
+            flow_preset: 0,
+            previous_update: Instant::now(),
+            snapshot: None,
+            snapshot_readback,
+            previous_snapshot: Instant::now(),
+        })
+    }
 
-        let camera_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Camera Buffer"),
-                contents: bytemuck::cast_slice(&[camera_uniform]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            }
-        );
+    /// Size of the window surface
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
 
+    /// The simulated flock
+    pub fn simulation(&self) -> &BoidSimulation {
+        &self.simulation
+    }
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: camera_buffer.as_entire_binding(),
-                }
-            ],
-            label: Some("camera_bind_group"),
-        });
+    /// The renderer drawing the flock to the window
+    pub fn renderer(&self) -> &BoidRenderer {
+        &self.renderer
+    }
 
-        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor{
-            label: Some("RenderPipelineLayout"),
-            bind_group_layouts: &[&camera_bind_group_layout],
-            push_constant_ranges: &[]
-        });
-        
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor{
-            label: Some("RenderPipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module:&shader,
-                entry_point: "vs_main",
-                buffers: &[
-                    wgpu::VertexBufferLayout{
-                        array_stride: std::mem::size_of::<Boid>() as u64,
-                        step_mode: wgpu::VertexStepMode::Instance,
-                        attributes: &wgpu::vertex_attr_array![ 0=>Float32x2, 1=>Float32x2, 2=>Float32x3]
-                    },
-                    wgpu::VertexBufferLayout{
-                        array_stride: 2 * 4,
-                        step_mode: wgpu::VertexStepMode::Vertex,
-                        attributes: &wgpu::vertex_attr_array![ 3=>Float32x2 ]
-                    }
-                ]
-            },
-            primitive:  wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            fragment: Some(wgpu::FragmentState{
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[wgpu::ColorTargetState{
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL
-                }]
-            }),
-            multiview: None
-        });
+    /// Has to be called whenever the window is resized
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            //Updating surface
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            self.surface.configure(&self.device, &self.config);
+            self.renderer.resize(&self.device, &self.queue, new_size);
+        }
+    }
 
+    /// Whether the device was lost, ran out of memory or failed a readback, in which case it has
+    /// to be recreated
+    pub fn device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::SeqCst)
+    }
 
-        let boid_vertex_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(BOID_VERTICES),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    // Runs the GPU work of a frame, flagging the device as lost instead of unwinding when wgpu
+    // panicked because of the loss. Any other panic carries on
+    fn guard_device_loss<T>(&mut self, work: impl FnOnce(&mut Self) -> T) -> Option<T> {
+        match panic::catch_unwind(AssertUnwindSafe(|| work(self))) {
+            Ok(result) => Some(result),
+            Err(payload) if is_device_loss_panic(&*payload) => {
+                log::error!("The GPU device was lost");
+                self.device_lost.store(true, Ordering::SeqCst);
+                None
             }
-        );
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
 
-        let boid_triangle_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(BOID_TRIANGLE),
-                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+    // A readback only fails to map once its device is gone
+    fn check_device_loss(&self, error: &anyhow::Error) {
+        if error.downcast_ref::<wgpu::BufferAsyncError>().is_some() {
+            self.device_lost.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Recreates the device with every pipeline and buffer, the flock starting over from its
+    /// latest snapshot and the cameras keeping their position. A running recording is stopped
+    pub fn recover(&mut self) -> anyhow::Result<()> {
+        log::warn!("Recreating the GPU device");
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.stop() {
+                Ok(frames) => log::warn!("Recording stopped after {} frames by the device loss", frames),
+                Err(e) => log::error!("{:?}", e),
             }
-        );
+        }
+        // Its staging buffers belong to the lost device, the samples already written are kept
+        if self.trajectory.take().is_some() {
+            log::warn!("Trajectory recording stopped by the device loss");
+        }
 
-        let initial_boid= &arr![Boid::rand_new();1000];
-        let boid_count = initial_boid.len() as u32;
+        let (adapter, device, queue) = pollster::block_on(request_device(&self.instance, &self.surface))?;
+        self.config.format = self.surface.get_preferred_format(&adapter).context("the window surface is not supported by the adapter")?;
+        self.surface.configure(&device, &self.config);
+        self.device_lost.store(false, Ordering::SeqCst);
+        watch_errors(&device, self.device_lost.clone());
 
+        let render_params = self.renderer.params().clone();
+        let simulation = self.simulation.recreate(&device, &queue, self.snapshot.as_deref())?;
+        let mut renderer = BoidRenderer::new(&device, &queue, self.config.format, self.size, &simulation, render_params);
+        *renderer.camera_mut() = self.renderer.camera().clone();
+        *renderer.orbit_camera_mut() = self.renderer.orbit_camera().clone();
+        renderer.write_camera(&queue, self.size);
+        self.gpu_timer = create_gpu_timer(&device, &queue);
+        self.profiling_frame = false;
+        // A metrics sample on its way back from the lost device is skipped
+        self.snapshot_readback = create_boid_readback(&device, &simulation, "Snapshot readback buffer");
+        self.metrics_readback = create_boid_readback(&device, &simulation, "Metrics readback buffer");
 
-        let simu_uniform = simulation_params.create_uniforms(0.0);
-        let params_buffer = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Simu params buffer"),
-            contents: bytemuck::cast_slice(&[simu_uniform]),
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM
-        });
+        // Dropping a lost device panics as it waits for it, it is leaked instead
+        std::mem::forget(std::mem::replace(&mut self.device, device));
+        self.queue = queue;
+        self.simulation = simulation;
+        self.renderer = renderer;
+        log::info!("GPU device recreated");
+        Ok(())
+    }
 
-        let mut boid_buffers = vec![];
-        for _ in 0..2 {
-            boid_buffers.push(device.create_buffer_init(
-                &wgpu::util::BufferInitDescriptor {
-                    label: Some("Boid Buffer"),
-                    contents: bytemuck::cast_slice(initial_boid),
-                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
-                }
-            ));
-        }
-
-        let boid_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Boid Bind Group Layout"),
-            entries: &[
-                BindGroupLayoutEntry{
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: BufferSize::new(std::mem::size_of::<SimuUniforms>() as u64)
-                    },
-                    count: None
+    /// Handles a window event, returning whether it was consumed
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::N),
+                    ..
                 },
-                BindGroupLayoutEntry{
-                    binding:1,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: BufferSize::new(std::mem::size_of::<Boid>() as u64 * boid_count as u64)
-                    },
-                    count: None
+                ..
+            } => {
+                // Switching between metric and topological neighbourhoods
+                let neighbourhood = self.simulation.params().neighbourhood.toggled();
+                self.simulation.set_neighbourhood(neighbourhood);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::F),
+                    ..
                 },
-                BindGroupLayoutEntry{
-                    binding:2,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: BufferSize::new(std::mem::size_of::<Boid>() as u64 * boid_count as u64)
-                    },
-                    count: None
+                ..
+            } => {
+                // Cycling through the flow fields
+                self.flow_preset = (self.flow_preset + 1) % self.flow_presets.len();
+                if let Err(e) = self.simulation.set_flow_field(&self.queue, self.flow_presets[self.flow_preset].clone()) {
+                    log::warn!("{:?}", e);
                 }
-            ]
-        });
-
-
-        let mut boid_bind_groups = vec![];
-        for i in 0..2 {
-            boid_bind_groups.push(device.create_bind_group(&BindGroupDescriptor{
-                label: Some(&*format!("Boid binding group {}", i)),
-                layout: &boid_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry{ binding: 0, resource: params_buffer.as_entire_binding() },
-                    wgpu::BindGroupEntry{ binding: 1, resource: boid_buffers[i].as_entire_binding() },
-                    wgpu::BindGroupEntry{ binding: 2, resource: boid_buffers[(i+1)%2].as_entire_binding() },
-                ]
-            }));
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::P),
+                    ..
+                },
+                ..
+            } => {
+                // Toggling path following, the paths start over when turned on
+                let follow_paths = !self.simulation.params().follow_paths;
+                self.simulation.set_follow_paths(&self.device, &self.queue, follow_paths);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::T),
+                    ..
+                },
+                ..
+            } => {
+                // Toggling the trails, which start from scratch when shown again
+                let params = self.renderer.params_mut();
+                params.trails = !params.trails;
+                self.renderer.clear_trails();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::C),
+                    ..
+                },
+                ..
+            } => {
+                // Cycling through the color modes
+                let params = self.renderer.params_mut();
+                params.color_mode = params.color_mode.next();
+                self.renderer.apply_params(&self.queue, self.simulation.params());
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::M),
+                    ..
+                },
+                ..
+            } => {
+                // Cycling through the colormaps
+                let params = self.renderer.params_mut();
+                params.colormap = params.colormap.next();
+                self.renderer.apply_params(&self.queue, self.simulation.params());
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::H),
+                    ..
+                },
+                ..
+            } => {
+                // Cycling through no heatmap, heatmap behind the boids and heatmap alone
+                let params = self.renderer.params_mut();
+                params.heatmap = params.heatmap.next();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::G),
+                    ..
+                },
+                ..
+            } => {
+                // Cycling through no debug overlay, the selected boids and the whole flock
+                let params = self.renderer.params_mut();
+                params.debug = params.debug.next();
+                self.renderer.apply_params(&self.queue, self.simulation.params());
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::B),
+                    ..
+                },
+                ..
+            } => {
+                // Toggling the background grid
+                let params = self.renderer.params_mut();
+                params.background = !params.background;
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::R),
+                    ..
+                },
+                ..
+            } => {
+                // Starting or stopping the recording
+                self.toggle_recording();
+                true
+            }
+            _ => self.camera_controller.process_events(event) || self.mouse_controller.process_events(event)
         }
+    }
 
-        let camera_controller = CameraController::new(1., 5.);
-
-        let compute_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor{
-            label: Some("Compute Pipeline Layout"),
-            bind_group_layouts: &[&boid_bind_group_layout],
-            push_constant_ranges: &[]
-        });
-
-        let compute_shader = device.create_shader_module(&ShaderModuleDescriptor{
-            label: Some("StepBoids"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("compute2.wgsl").into())
-        });
-
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor{
-            label: Some("Compute Pipeline"),
-            layout: Some(&compute_pipeline_layout),
-            module: &compute_shader,
-            entry_point: "step"
-        });
-
-        let workgroup_count = ((boid_count as f32) / (64 as f32)).ceil() as u32;
-
+    /// Window title showing the statistics and the length of the scale bar, only when it needs
+    /// refreshing
+    pub fn title(&mut self) -> Option<String> {
+        let title = self.stats.title()?;
+        Some(match self.renderer.scale_bar_length() {
+            Some(length) => {
+                // Only the decimals of its single significant digit, 0.05 rather than 0.050000004
+                let decimals = (-length.log10().floor()).max(0.0) as usize;
+                format!("{} | scale bar {:.*}", title, decimals, length)
+            }
+            None => title,
+        })
+    }
 
-        Self {
-            surface,
-            device,
-            queue,
-            config,
-            size,
-            simulation_params,
-            render_pipeline,
-            compute_pipeline,
-            camera,
-            camera_uniform,
-            camera_bind_group,
-            boid_bind_groups,
-            simu_uniform,
-            workgroup_count,
-            boid_vertex_buffer,
-            boid_triangle_buffer,
-            boid_buffers,
-            boid_count,
-            camera_buffer,
-            camera_controller,
-            params_buffer,
-            previous_update: Instant::now(),
-            frame:0
+    /// Starts recording the frames with AppParams::recording, or stops the running recording
+    pub fn toggle_recording(&mut self) {
+        let recording = &self.app_params.recording;
+        match self.recorder.take() {
+            Some(recorder) => match recorder.stop() {
+                Ok(frames) => log::info!("Recording stopped after {} frames", frames),
+                Err(e) => log::error!("{:?}", e),
+            },
+            None => {
+                let recorder = Recorder::start(
+                    &self.device,
+                    self.renderer.format(),
+                    self.renderer.sample_count(),
+                    self.renderer.uses_depth(),
+                    recording,
+                );
+                match recorder {
+                    Ok(recorder) => {
+                        log::info!("Recording {:?}", recording.output);
+                        self.recorder = Some(recorder);
+                    }
+                    Err(e) => log::error!("{:?}", e),
+                }
+            }
         }
-
     }
 
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            //Updating surface
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-
-            // Updating the camera
-            self.camera_uniform.update_view_proj(&self.camera,self.size);
-            self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]))
-        }
+    /// Replaces the paths, every boid starting over from the first waypoint of its path
+    pub fn set_paths(&mut self, paths: Vec<WaypointPath>) {
+        self.simulation.set_paths(&self.device, &self.queue, paths);
     }
 
-    pub fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera_controller.process_events(event)
+    /// Replaces the flow field, failing when it has more cells than the largest flow preset
+    pub fn set_flow_field(&mut self, flow_field: FlowField) -> anyhow::Result<()> {
+        self.simulation.set_flow_field(&self.queue, flow_field)
     }
 
+    /// Advances the simulation by the time elapsed since the previous update, recreating the
+    /// device first when it was lost
     pub fn update(&mut self) {
+        self.guard_device_loss(Self::update_frame);
+    }
+
+    fn update_frame(&mut self) {
+        if self.device_lost() {
+            // Tried again on the next update when the device can't be recreated yet
+            if let Err(e) = self.recover() {
+                log::error!("{:?}", e);
+                return;
+            }
+        }
+        self.read_gpu_times();
+        self.read_snapshot();
+        self.write_metrics(false);
+        if let Some(mut trajectory) = self.trajectory.take() {
+            match trajectory.write_ready(&self.device) {
+                Ok(()) => self.trajectory = Some(trajectory),
+                Err(e) => {
+                    log::error!("Trajectory recording stopped : {:?}", e);
+                    self.check_device_loss(&e);
+                }
+            }
+        }
         let now = Instant::now();
-        let frame = self.frame;
-        let delta_time = (now-self.previous_update).as_secs_f32();
+        let frame_time = (now-self.previous_update).as_secs_f32();
+        // Recordings advance by fixed steps whatever the time it takes to render them
+        let delta_time = match self.recorder {
+            Some(_) => 1.0 / self.app_params.recording.fps,
+            None => frame_time,
+        };
         self.previous_update = now;
-        self.frame+=1;
 
-        print!("\x1B[0K\x1B[GFrame : {}, Delta T : {:4}", frame, delta_time);
-
-        if self.camera_controller.update_camera(&mut self.camera){
-            self.camera_uniform.update_view_proj(&self.camera, self.size);
-            self.queue.write_buffer(&self.camera_buffer,0, bytemuck::cast_slice(&[self.camera_uniform]))
+        let camera_updated = match self.simulation.dimensions() {
+            Dimensions::Two => self.camera_controller.update_camera(self.renderer.camera_mut()),
+            Dimensions::Three => self.camera_controller.update_orbit_camera(self.renderer.orbit_camera_mut()),
+        };
+        if camera_updated {
+            self.renderer.write_camera(&self.queue, self.size);
         }
 
-        self.simu_uniform.update(delta_time * 2.0);
-        self.queue.write_buffer(&self.params_buffer, 0 , bytemuck::cast_slice(&[self.simu_uniform]));
+        let sim_step = delta_time * 2.0 * self.simulation.params().step_mult;
+        self.stats.record(frame_time, sim_step);
+        // The cursor can only be brought back to the world in 2D
+        if self.simulation.dimensions() == Dimensions::Two {
+            let mouse_position = self.renderer.camera().screen_to_world(self.mouse_controller.cursor(), self.size);
+            let mouse_force = self.mouse_controller.force_sign() * self.simulation.params().mouse_strength;
+            self.simulation.set_mouse(mouse_position, mouse_force);
+        }
+        self.simulation.set_debug_vectors(self.renderer.params().debug != DebugView::Off);
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor{
             label:Some("Compute Encoder")
         });
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor{ label: None });
-            compute_pass.set_pipeline(&self.compute_pipeline);
-            compute_pass.set_bind_group(0,&self.boid_bind_groups[(frame % 2) as usize],&[]);
-            compute_pass.dispatch(self.workgroup_count,1, 1)
+        // Frames are profiled whenever the timestamps of the previous one were read back
+        let timer = self.gpu_timer.as_ref().filter(|timer| !timer.is_reading());
+        if let Some(timer) = timer {
+            timer.start(&mut encoder, COMPUTE_SPAN);
+        }
+        self.simulation.step(&self.queue, &mut encoder, sim_step);
+        if let Some(timer) = timer {
+            timer.stop(&mut encoder, COMPUTE_SPAN);
+        }
+        self.profiling_frame = timer.is_some();
+        if let Err(e) = self.renderer.update(&self.queue, &mut encoder, &self.simulation) {
+            log::error!("{:?}", e);
+        }
+        let trajectory_interval = self.app_params.trajectory.interval.max(1);
+        if self.simulation.step_count() % trajectory_interval == 0 {
+            if let Some(mut trajectory) = self.trajectory.take() {
+                match trajectory.copy_boids(&self.device, &mut encoder, &self.simulation, self.stats.sim_time()) {
+                    Ok(()) => self.trajectory = Some(trajectory),
+                    Err(e) => {
+                        log::error!("Trajectory recording stopped : {:?}", e);
+                        self.check_device_loss(&e);
+                    }
+                }
+            }
+        }
+        let metrics_interval = self.app_params.metrics.interval.max(1);
+        if self.metrics.is_some() && self.simulation.step_count() % metrics_interval == 0 {
+            // Waiting for the previous sample rather than skipping this one
+            if !self.metrics_readback.is_free() {
+                self.write_metrics(true);
+            }
+            let boids = &self.simulation.boid_buffers()[self.simulation.current_buffer()];
+            self.metrics_readback.copy(&mut encoder, boids, (self.simulation.step_count(), self.stats.sim_time()));
+        }
+        if now - self.previous_snapshot >= SNAPSHOT_INTERVAL && self.snapshot_readback.is_free() {
+            self.previous_snapshot = now;
+            let boids = &self.simulation.boid_buffers()[self.simulation.current_buffer()];
+            self.snapshot_readback.copy(&mut encoder, boids, ());
         }
         self.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(trajectory) = &mut self.trajectory {
+            trajectory.start_reading();
+        }
+        self.snapshot_readback.start_reading();
+        self.metrics_readback.start_reading();
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError>{
+    /// Draws the flock to the window, and to the recording when one is running. Nothing is
+    /// drawn when the device gets lost, the next update recreating it
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.guard_device_loss(Self::render_frame).unwrap_or(Ok(()))
+    }
+
+    fn render_frame(&mut self) -> Result<(), wgpu::SurfaceError>{
+        if let Some(mut recorder) = self.recorder.take() {
+            match self.record_frame(&mut recorder) {
+                Ok(()) => self.recorder = Some(recorder),
+                Err(e) => {
+                    log::error!("Recording stopped : {:?}", e);
+                    let _ = recorder.stop();
+                }
+            }
+        }
+
         let output = self.surface.get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
-
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: None,
-            });
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.boid_buffers[0].slice(..));
-            render_pass.set_vertex_buffer(1, self.boid_vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.boid_triangle_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..6,0,0..self.boid_count);
+        let timer = match &mut self.gpu_timer {
+            Some(timer) if self.profiling_frame => Some(timer),
+            _ => None,
+        };
+        if let Some(timer) = &timer {
+            timer.start(&mut encoder, RENDER_SPAN);
+        }
+        if let Err(e) = self.renderer.render(&mut encoder, &view, &self.simulation) {
+            log::error!("{:?}", e);
+        }
+        if let Some(timer) = &timer {
+            timer.stop(&mut encoder, RENDER_SPAN);
+            timer.resolve(&mut encoder);
         }
 
         // submit will accept anything that implements IntoIter
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
+        if let Some(timer) = timer {
+            timer.request_read();
+        }
+        self.profiling_frame = false;
 
         std::thread::sleep(Duration::from_millis(16));
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    // Adds the boids copied for the metrics to them once they reached the CPU, or waiting for
+    // them. The metrics stop when they can't be written
+    fn write_metrics(&mut self, wait: bool) {
+        let ((step, sim_time), boids) = match self.metrics_readback.try_read(&self.device, wait) {
+            Some(Ok(sample)) => sample,
+            Some(Err(e)) => {
+                let e = anyhow::Error::new(e).context("could not read the boids of a metrics sample back");
+                log::warn!("{:?}", e);
+                self.check_device_loss(&e);
+                return;
+            }
+            None => return,
+        };
+        if let Some(mut metrics) = self.metrics.take() {
+            let cluster_radius = self.app_params.metrics.cluster_radius;
+            let result = FlockMetrics::from_boids(self.simulation.dimensions(), &boids, cluster_radius)
+                .and_then(|sample| metrics.write(step, sim_time, &sample));
+            match result {
+                Ok(()) => self.metrics = Some(metrics),
+                Err(e) => log::error!("Metrics stopped : {:?}", e),
+            }
+        }
+    }
+
+    // Hands the GPU times of the latest profiled frame to the stats once they reached the CPU
+    fn read_gpu_times(&mut self) {
+        let result = match &mut self.gpu_timer {
+            Some(timer) => timer.try_read(&self.device),
+            None => None,
+        };
+        match result {
+            Some(Ok(times)) => self.stats.record_gpu(times[COMPUTE_SPAN as usize] as f32, times[RENDER_SPAN as usize] as f32),
+            Some(Err(e)) => {
+                log::warn!("{:?}", e);
+                self.check_device_loss(&e);
+            }
+            None => {}
+        }
+    }
+
+    // Keeps the boids copied for the snapshot once they reached the CPU
+    fn read_snapshot(&mut self) {
+        match self.snapshot_readback.try_read(&self.device, false).map(|result| result.context("could not snapshot the boids")) {
+            Some(Ok(((), boids))) => self.snapshot = Some(boids),
+            Some(Err(e)) => {
+                log::warn!("{:?}", e);
+                self.check_device_loss(&e);
+            }
+            None => {}
+        }
+    }
+
+    // Renders the frame at the recording size and writes it out
+    fn record_frame(&mut self, recorder: &mut Recorder) -> anyhow::Result<()> {
+        self.renderer.write_camera(&self.queue, recorder.size());
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Recording Encoder"),
+        });
+        self.renderer.encode_render_pass(&mut encoder, &self.simulation, recorder.view(), recorder.msaa_view(), recorder.depth_view())?;
+        recorder.copy_frame(&mut encoder);
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.renderer.write_camera(&self.queue, self.size);
+
+        recorder.save_frame(&self.device)
+    }
+}
+
+impl Drop for ApplicationState {
+    // Stops the recording and writes the metrics and trajectory samples still on their way back
+    fn drop(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.stop() {
+                Ok(frames) => log::info!("Recording stopped after {} frames", frames),
+                Err(e) => log::error!("{:?}", e),
+            }
+        }
+        self.write_metrics(true);
+        if let Some(trajectory) = self.trajectory.take() {
+            match trajectory.stop(&self.device) {
+                Ok(samples) => log::info!("Trajectory recorded over {} samples", samples),
+                Err(e) => log::error!("{:?}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panic_payload(f: impl FnOnce() + panic::UnwindSafe) -> Box<dyn Any + Send> {
+        panic::catch_unwind(f).unwrap_err()
+    }
+
+    #[test]
+    fn device_loss_panics() {
+        let cause = "parent device is lost";
+        assert!(is_device_loss_panic(&*panic_payload(|| panic!("Error in Queue::submit: {}", cause))));
+        assert!(is_device_loss_panic(&*panic_payload(|| panic!("Error in Device::poll: GPU got stuck :("))));
+        assert!(!is_device_loss_panic(&*panic_payload(|| panic!("Error in Queue::write_buffer: buffer is destroyed"))));
+        assert!(!is_device_loss_panic(&*panic_payload(|| panic!("{}", cause))));
+    }
+}
@@ -0,0 +1,152 @@
+use wgpu::{Device, Queue, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferSize, PipelineLayoutDescriptor, ShaderModuleDescriptor};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use bytemuck::{Pod, Zeroable};
+use crate::application::RenderParams;
+use crate::camera::Camera;
+
+// Minor grid lines closer than this are hidden
+const MIN_GRID_PIXELS: f32 = 12.0;
+// Preferred length of the scale bar
+const SCALE_BAR_PIXELS: f32 = 150.0;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct BackgroundUniforms {
+    viewport: [f32; 2],
+    grid_spacing: f32,
+    grid_fade: f32,
+    world_half_size: f32,
+    scale_bar_length: f32,
+}
+
+impl BackgroundUniforms {
+    fn new(camera: &Camera, size: winit::dpi::PhysicalSize<u32>, world_half_size: f32) -> Self {
+        let scaling = camera.build_scaling(size);
+        let pixel = 2.0 / (scaling[0] * size.width as f32);
+
+        // Powers of ten, the minor lines fading in between two zoom levels
+        let min_spacing = MIN_GRID_PIXELS * pixel;
+        let grid_spacing = 10_f32.powf(min_spacing.log10().ceil());
+        let grid_fade = (grid_spacing / min_spacing).log10().clamp(0.0, 1.0);
+
+        // 1, 2 or 5 times a power of ten
+        let target = SCALE_BAR_PIXELS * pixel;
+        let magnitude = 10_f32.powf(target.log10().floor());
+        let scale_bar_length = [5.0, 2.0, 1.0].iter()
+            .map(|m| m * magnitude)
+            .find(|&length| length <= target)
+            .unwrap_or(magnitude);
+
+        Self {
+            viewport: [size.width as f32, size.height as f32],
+            grid_spacing,
+            grid_fade,
+            world_half_size,
+            scale_bar_length,
+        }
+    }
+}
+
+// World space grid adapting to the zoom, origin axes, world boundary and scale bar,
+// drawn behind the 2D boids with the same camera
+pub struct Background {
+    render_pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    uniform: BackgroundUniforms,
+}
+
+impl Background {
+    pub fn new(
+        device: &Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        camera: &Camera,
+        size: winit::dpi::PhysicalSize<u32>,
+        render_params: &RenderParams,
+    ) -> Self {
+        let uniform = BackgroundUniforms::new(camera, size, render_params.world_half_size);
+        let params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Background params buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Background Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(std::mem::size_of::<BackgroundUniforms>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Background binding group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+            ],
+        });
+
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("RenderBackground"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("background.wgsl").into()),
+        });
+        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Background Render Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Background Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_background",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: render_params.msaa_samples,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_background",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            multiview: None,
+        });
+
+        Self { render_pipeline, bind_group, params_buffer, uniform }
+    }
+
+    // Has to be called whenever the camera moves or the window is resized
+    /// Length of the scale bar in world units
+    pub fn scale_bar_length(&self) -> f32 {
+        self.uniform.scale_bar_length
+    }
+
+    pub fn update(&mut self, queue: &Queue, camera: &Camera, size: winit::dpi::PhysicalSize<u32>) {
+        self.uniform = BackgroundUniforms::new(camera, size, self.uniform.world_half_size);
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Expects the camera to be bound to group 0
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
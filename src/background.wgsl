@@ -0,0 +1,85 @@
+// World space grid, origin axes, world boundary and scale bar drawn behind the 2D boids
+
+struct CameraUniform {
+    origin:vec2<f32>;
+    scaling:vec2<f32>;
+};
+
+struct BackgroundParams {
+    viewport: vec2<f32>;
+    gridSpacing: f32;    // minor lines, the major ones being 10 times further apart
+    gridFade: f32;       // opacity of the minor lines, fading in as the zoom grows
+    worldHalfSize: f32;
+    scaleBarLength: f32; // world units
+};
+
+[[group(0), binding(0)]]
+var<uniform> camera: CameraUniform;
+[[group(1), binding(0)]]
+var<uniform> background: BackgroundParams;
+
+struct BackgroundOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+};
+
+// Triangle covering the whole screen
+[[stage(vertex)]]
+fn vs_background([[builtin(vertex_index)]] vertex: u32) -> BackgroundOutput {
+    var corners = array<vec2<f32>, 3>(vec2<f32>(-1.0, -1.0), vec2<f32>(3.0, -1.0), vec2<f32>(-1.0, 3.0));
+    var out: BackgroundOutput;
+    out.clip_position = vec4<f32>(corners[vertex], 0.0, 1.0);
+    return out;
+}
+
+// How much of a line of the given width in pixels covers the fragment, the lines being at
+// every multiple of spacing
+fn lines(world: vec2<f32>, spacing: f32, pixel: vec2<f32>, width: f32) -> f32 {
+    let distance = abs(fract(world / spacing + 0.5) - 0.5) * spacing / pixel;
+    let coverage = clamp(width * 0.5 + 0.5 - distance, vec2<f32>(0.0), vec2<f32>(1.0));
+    return max(coverage.x, coverage.y);
+}
+
+fn over(color: vec4<f32>, layer: vec3<f32>, alpha: f32) -> vec4<f32> {
+    return vec4<f32>(mix(color.rgb, layer, alpha), max(color.a, alpha));
+}
+
+[[stage(fragment)]]
+fn fs_background(in: BackgroundOutput) -> [[location(0)]] vec4<f32> {
+    let screen = in.clip_position.xy;
+    let clip = vec2<f32>(screen.x / background.viewport.x * 2.0 - 1.0, 1.0 - screen.y / background.viewport.y * 2.0);
+    let world = clip / camera.scaling + camera.origin;
+    // World size of a pixel
+    let pixel = 2.0 / (camera.scaling * background.viewport);
+
+    var color = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    let grey = vec3<f32>(0.5, 0.5, 0.5);
+    color = over(color, grey, 0.15 * background.gridFade * lines(world, background.gridSpacing, pixel, 1.0));
+    color = over(color, grey, 0.3 * lines(world, background.gridSpacing * 10.0, pixel, 1.0));
+
+    // Axes, x in red and y in green
+    let axes = abs(world) / pixel;
+    color = over(color, vec3<f32>(0.8, 0.25, 0.25), clamp(1.25 - axes.y, 0.0, 1.0));
+    color = over(color, vec3<f32>(0.25, 0.8, 0.25), clamp(1.25 - axes.x, 0.0, 1.0));
+
+    // World boundary
+    let bound = abs(abs(world) - background.worldHalfSize) / pixel;
+    let inside = abs(world) <= vec2<f32>(background.worldHalfSize) + pixel;
+    var boundary = 0.0;
+    if (inside.y) {
+        boundary = max(boundary, clamp(1.5 - bound.x, 0.0, 1.0));
+    }
+    if (inside.x) {
+        boundary = max(boundary, clamp(1.5 - bound.y, 0.0, 1.0));
+    }
+    color = over(color, vec3<f32>(0.7, 0.7, 0.9), 0.6 * boundary);
+
+    // Scale bar in the bottom left corner, with a tick at each end
+    let barLength = background.scaleBarLength / pixel.x;
+    let bar = vec2<f32>(screen.x - 20.0, background.viewport.y - 20.0 - screen.y);
+    let inBar = bar.x >= 0.0 && bar.x <= barLength && bar.y >= 0.0 && bar.y <= 3.0;
+    let inTicks = (bar.x >= 0.0 && bar.x <= 2.0 || bar.x >= barLength - 2.0 && bar.x <= barLength) && bar.y >= 0.0 && bar.y <= 10.0;
+    if (inBar || inTicks) {
+        color = over(color, vec3<f32>(1.0, 1.0, 1.0), 0.9);
+    }
+    return color;
+}
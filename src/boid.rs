@@ -5,14 +5,17 @@ use rand::{
 };
 use rand_pcg::{Lcg128Xsl64};
 use std::sync::Mutex;
+use anyhow::bail;
 use bytemuck::{Pod, Zeroable};
+/// 2D boid, laid out as in the boid buffers of the simulation
 #[repr(C, align(16))]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct Boid{
     position:[f32;2],
     speed:[f32;2],
-    color:[f32;3],
-    _pad:[f32; 1],
+    color:[f32;4],
+    species:u32,
+    _pad:[u32;3],
 }
 
 lazy_static!{
@@ -23,17 +26,120 @@ lazy_static!{
 }
 
 impl Boid {
-    pub fn new(position: [f32;2], speed: [f32;2], color: [f32;3])->Self{
-        Boid{ position, speed,  color, _pad:[0.0] }
+    /// A boid of the given species, the alpha of its color setting its opacity
+    pub fn new(position: [f32;2], speed: [f32;2], color: [f32;4], species: u32)->Self{
+        Boid{ position, speed,  color, species, _pad: [0;3] }
     }
 
+    /// A boid at a random position within 10 of the origin, with a random speed and color.
+    /// The boids are drawn from a generator with a fixed seed, the same flocks being created
+    /// in the same order on every run
     pub fn rand_new()->Self{
         let rng = &mut *RNG.lock().unwrap();
         Boid{
             position: [POS_DIST.sample(rng), POS_DIST.sample(rng)],
             speed: [SPEED_DIST.sample(rng), SPEED_DIST.sample(rng)],
-            color: [COLOR_DIST.sample(rng), COLOR_DIST.sample(rng), COLOR_DIST.sample(rng)],
-            _pad: [0.0]
+            color: [COLOR_DIST.sample(rng), COLOR_DIST.sample(rng), COLOR_DIST.sample(rng), 1.0],
+            species: 0,
+            _pad: [0;3]
         }
     }
-}
\ No newline at end of file
+
+    /// The same boid in another species
+    pub fn with_species(self, species: u32)->Self{
+        Boid{ species, ..self }
+    }
+
+    /// Position in world units
+    pub fn position(&self)->[f32;2]{
+        self.position
+    }
+
+    /// Velocity in world units per unit of simulated time
+    pub fn speed(&self)->[f32;2]{
+        self.speed
+    }
+
+    /// RGBA color, the alpha being only used when drawing
+    pub fn color(&self)->[f32;4]{
+        self.color
+    }
+
+    /// Opacity of the boid when alpha blending is enabled
+    pub fn with_alpha(mut self, alpha: f32)->Self{
+        self.color[3] = alpha;
+        self
+    }
+}
+
+/// 3D boid, vec3 being 16 bytes aligned on the GPU side
+#[repr(C, align(16))]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct Boid3{
+    position:[f32;3],
+    _pad0:f32,
+    speed:[f32;3],
+    _pad1:f32,
+    color:[f32;4],
+    species:u32,
+    _pad2:[u32;3],
+}
+
+impl Boid3 {
+    /// A boid of the given species, the alpha of its color setting its opacity
+    pub fn new(position: [f32;3], speed: [f32;3], color: [f32;4], species: u32)->Self{
+        Boid3{ position, _pad0: 0.0, speed, _pad1: 0.0, color, species, _pad2: [0;3] }
+    }
+
+    /// A boid at a random position within 10 of the origin, with a random speed and color,
+    /// drawn from the same generator as Boid::rand_new
+    pub fn rand_new()->Self{
+        let rng = &mut *RNG.lock().unwrap();
+        Boid3{
+            position: [POS_DIST.sample(rng), POS_DIST.sample(rng), POS_DIST.sample(rng)],
+            _pad0: 0.0,
+            speed: [SPEED_DIST.sample(rng), SPEED_DIST.sample(rng), SPEED_DIST.sample(rng)],
+            _pad1: 0.0,
+            color: [COLOR_DIST.sample(rng), COLOR_DIST.sample(rng), COLOR_DIST.sample(rng), 1.0],
+            species: 0,
+            _pad2: [0;3]
+        }
+    }
+
+    /// The same boid in another species
+    pub fn with_species(self, species: u32)->Self{
+        Boid3{ species, ..self }
+    }
+
+    /// Position in world units
+    pub fn position(&self)->[f32;3]{
+        self.position
+    }
+
+    /// Velocity in world units per unit of simulated time
+    pub fn speed(&self)->[f32;3]{
+        self.speed
+    }
+
+    /// RGBA color, the alpha being only used when drawing
+    pub fn color(&self)->[f32;4]{
+        self.color
+    }
+
+    /// Opacity of the boid when alpha blending is enabled
+    pub fn with_alpha(mut self, alpha: f32)->Self{
+        self.color[3] = alpha;
+        self
+    }
+}
+
+/// Boids read back from a buffer, copied so that they are aligned
+pub(crate) fn decode_boids<T: Pod>(bytes: &[u8]) -> anyhow::Result<Vec<T>> {
+    let size = std::mem::size_of::<T>();
+    if bytes.len() % size != 0 {
+        bail!("{} bytes don't make whole boids of {} bytes", bytes.len(), size);
+    }
+    let mut boids = vec![T::zeroed(); bytes.len() / size];
+    bytemuck::cast_slice_mut(&mut boids).copy_from_slice(bytes);
+    Ok(boids)
+}
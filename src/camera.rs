@@ -1,25 +1,45 @@
 use bytemuck::{Pod, Zeroable};
 use winit::event::{WindowEvent, KeyboardInput, VirtualKeyCode, ElementState};
+use cgmath::{Deg, Matrix4, Point3, Vector3};
 
-#[derive(Debug)]
+/// Orthographic camera panning and zooming over the plane, used by the 2D mode
+#[derive(Clone, Debug)]
 pub struct Camera{
     origin:[f32; 2],
     scaling:[f32; 2]
 }
 
 impl Camera {
+    /// A camera centered on the origin
     pub fn new()->Self{
         Self{ origin: [0.0,0.0], scaling: [80.0, 80.0] }
     }
 
+    /// Factors from world to clip coordinates for a window of the given size
     pub fn build_scaling(&self, size:winit::dpi::PhysicalSize<u32>) -> [f32; 2] {
-        return [self.scaling[0] / size.width as f32, self.scaling[1] / size.height as f32]
+        [self.scaling[0] / size.width as f32, self.scaling[1] / size.height as f32]
+    }
+
+    /// Inverse of the projection done in draw.wgsl, from window pixels to world coordinates
+    pub fn screen_to_world(&self, position: winit::dpi::PhysicalPosition<f64>, size:winit::dpi::PhysicalSize<u32>) -> [f32; 2] {
+        let scaling = self.build_scaling(size);
+        let clip = [
+            position.x as f32 / size.width as f32 * 2.0 - 1.0,
+            1.0 - position.y as f32 / size.height as f32 * 2.0,
+        ];
+        [clip[0] / scaling[0] + self.origin[0], clip[1] / scaling[1] + self.origin[1]]
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
-pub struct CameraUniform{
+pub(crate) struct CameraUniform{
     origin: [f32; 2],
     scaling: [f32; 2]
 }
@@ -36,7 +56,86 @@ impl CameraUniform {
     }
 }
 
-pub struct CameraController {
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Perspective camera orbiting around a target, used by the 3D mode
+#[derive(Clone, Debug)]
+pub struct OrbitCamera{
+    target:[f32; 3],
+    distance:f32,
+    yaw:f32,
+    pitch:f32,
+    fovy:f32,
+}
+
+impl OrbitCamera {
+    /// A camera looking at the origin from 60 away, slightly above the plane
+    pub fn new()->Self{
+        Self{ target: [0.0, 0.0, 0.0], distance: 60.0, yaw: 0.0, pitch: 20.0, fovy: 45.0 }
+    }
+
+    /// Position of the camera
+    pub fn eye(&self) -> [f32; 3] {
+        let (yaw, pitch) = (self.yaw.to_radians(), self.pitch.to_radians());
+        [
+            self.target[0] + self.distance * pitch.cos() * yaw.sin(),
+            self.target[1] - self.distance * pitch.cos() * yaw.cos(),
+            self.target[2] + self.distance * pitch.sin(),
+        ]
+    }
+
+    /// View and projection to the clip coordinates of wgpu for a window of the given size
+    pub fn build_view_projection_matrix(&self, size:winit::dpi::PhysicalSize<u32>) -> Matrix4<f32> {
+        let view = Matrix4::look_at_rh(Point3::from(self.eye()), Point3::from(self.target), Vector3::unit_z());
+        let aspect = size.width as f32 / size.height as f32;
+        let proj = cgmath::perspective(Deg(self.fovy), aspect, 0.1, 1000.0);
+        OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+}
+
+impl Default for OrbitCamera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// cgmath targets OpenGL's [-1, 1] depth range where wgpu uses [0, 1]
+#[rustfmt::skip]
+const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub(crate) struct OrbitCameraUniform{
+    view_proj: [[f32; 4]; 4]
+}
+
+impl OrbitCameraUniform {
+    pub fn new() -> Self{
+        use cgmath::SquareMatrix;
+        Self{ view_proj: Matrix4::identity().into() }
+    }
+
+    pub fn update_view_proj(&mut self, camera: &OrbitCamera, size:winit::dpi::PhysicalSize<u32>) {
+        self.view_proj = camera.build_view_projection_matrix(size).into();
+    }
+}
+
+impl Default for OrbitCameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) struct CameraController {
     move_speed: f32,
     zoom_speed: f32,
     is_forward_pressed: bool,
@@ -127,11 +226,36 @@ impl CameraController {
         }
 
         if self.is_zoom_pressed && !self.is_unzoom_pressed {
-            camera.scaling = [camera.scaling[0]*(1.0-self.zoom_speed), camera.scaling[1]*(1.0-self.zoom_speed)]
+            camera.scaling = [camera.scaling[0]*(1.0+self.zoom_speed), camera.scaling[1]*(1.0+self.zoom_speed)];
+            return true
         } else if self.is_unzoom_pressed {
-            camera.scaling = [camera.scaling[0]*(1.0+self.zoom_speed), camera.scaling[1]*(1.0+self.zoom_speed)]
+            camera.scaling = [camera.scaling[0]*(1.0-self.zoom_speed), camera.scaling[1]*(1.0-self.zoom_speed)];
+            return true
         }
 
         false
     }
+
+    // Same keys as the 2D camera : the arrows orbit around the target and +/- move closer or further
+    pub(crate) fn update_orbit_camera(&self, camera: &mut OrbitCamera) -> bool {
+        let mut updated = false;
+        if self.is_left_pressed != self.is_right_pressed {
+            let direction = if self.is_left_pressed { -1.0 } else { 1.0 };
+            camera.yaw += direction * self.move_speed;
+            updated = true;
+        }
+        if self.is_forward_pressed != self.is_backward_pressed {
+            let direction = if self.is_forward_pressed { 1.0 } else { -1.0 };
+            camera.pitch = (camera.pitch + direction * self.move_speed).clamp(-89.0, 89.0);
+            updated = true;
+        }
+        if self.is_zoom_pressed && !self.is_unzoom_pressed {
+            camera.distance *= 1.0 - self.zoom_speed;
+            updated = true;
+        } else if self.is_unzoom_pressed {
+            camera.distance *= 1.0 + self.zoom_speed;
+            updated = true;
+        }
+        updated
+    }
 }
@@ -0,0 +1,70 @@
+// Boid coloring shared by draw.wgsl and draw3.wgsl, prepended to them
+
+struct RenderUniform {
+    colorMode: u32;     // 0 stored, 1 speed, 2 heading, 3 local density, 4 species
+    colormap: u32;      // 0 viridis, 1 magma, 2 hsv
+    maxSpeed: f32;
+    maxDensity: f32;
+    speciesCount: u32;
+    boidSize: f32;
+    shapeCount: u32;    // species s is drawn with the shape s % shapeCount
+};
+
+[[group(0), binding(1)]]
+var<uniform> render: RenderUniform;
+
+fn hsv(h: f32) -> vec3<f32> {
+    return clamp(abs(fract(vec3<f32>(h, h + 2.0 / 3.0, h + 1.0 / 3.0)) * 6.0 - 3.0) - 1.0, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Polynomial fits of matplotlib's viridis and magma
+fn viridis(t: f32) -> vec3<f32> {
+    let c0 = vec3<f32>(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
+    let c1 = vec3<f32>(0.1050930431085774, 1.404613529898575, 1.384590162594685);
+    let c2 = vec3<f32>(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
+    let c3 = vec3<f32>(-4.634230498983486, -5.799100973351585, -19.33244095627987);
+    let c4 = vec3<f32>(6.228269936347081, 14.17993336680509, 56.69055260068105);
+    let c5 = vec3<f32>(4.776384997670288, -13.74514537774601, -65.35303263337234);
+    let c6 = vec3<f32>(-5.435455855934631, 4.645852612178535, 26.3124352495832);
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+fn magma(t: f32) -> vec3<f32> {
+    let c0 = vec3<f32>(-0.002136485053939582, -0.000749655052795221, -0.005386127855323933);
+    let c1 = vec3<f32>(0.2516605407371642, 0.6775232436837668, 2.494026599312351);
+    let c2 = vec3<f32>(8.353717279216625, -3.577719514958484, 0.3144679030132573);
+    let c3 = vec3<f32>(-27.66873308576866, 14.26473078096533, -13.64921318813922);
+    let c4 = vec3<f32>(52.17613981234068, -27.94360607168351, 12.94416944238394);
+    let c5 = vec3<f32>(-50.76852536473588, 29.04658282127291, 4.23415299384598);
+    let c6 = vec3<f32>(18.65570506591883, -11.48977351997711, -5.601961508734096);
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+fn colormap(t: f32) -> vec3<f32> {
+    let t = clamp(t, 0.0, 1.0);
+    if (render.colormap == 1u) {
+        return magma(t);
+    }
+    if (render.colormap == 2u) {
+        return hsv(t);
+    }
+    return viridis(t);
+}
+
+fn boidColor(stored: vec3<f32>, vel: vec3<f32>, neighbours: u32, species: u32) -> vec3<f32> {
+    if (render.colorMode == 1u) {
+        return colormap(length(vel) / render.maxSpeed);
+    }
+    if (render.colorMode == 2u) {
+        // The heading is cyclic so it always goes around the hue wheel
+        return hsv(atan2(vel.y, vel.x) / 6.28318530718 + 0.5);
+    }
+    if (render.colorMode == 3u) {
+        return colormap(f32(neighbours) / render.maxDensity);
+    }
+    if (render.colorMode == 4u) {
+        return colormap(f32(species) / f32(max(render.speciesCount, 2u) - 1u));
+    }
+    return stored;
+}
+
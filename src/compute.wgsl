@@ -1,8 +1,9 @@
-struct Boid{ //align(16) size(32)
+struct Boid{ //align(16) size(48)
     position:vec2<f32>; // offset(0)  align(8) size(8)
     speed:vec2<f32>;    // offset(8)  align(8) size(8)
-    color:vec3<f32>;    // offset(16) align(16) size(12)
-    // padding(4)
+    color:vec4<f32>;    // offset(16) align(16) size(16), alpha only used when rendering
+    species:u32;        // offset(32) align(4)  size(4)
+    // padding(12)
 };
 
 struct Params {
@@ -15,18 +16,115 @@ struct Params {
     cohesionScale: f32;
     colorMult: f32;
     centerAttraction: f32;
+    topological: u32;
+    separationK: u32;
+    alignementK: u32;
+    cohesionK: u32;
+    minSpeed: f32;
+    maxSpeed: f32;
+    inertia: f32;
+    maxSteeringForce: f32;
+    flowOriginX: f32;
+    flowOriginY: f32;
+    flowCellSize: f32;
+    flowWidth: u32;
+    flowHeight: u32;
+    flowScale: f32;
+    pathCount: u32;
+    goalScale: f32;
+    mouseX: f32;
+    mouseY: f32;
+    mouseForce: f32;
+    mouseRadius: f32;
+    debugVectors: u32;
 };
 
+// Must match MAX_NEIGHBOURS in application.rs
+let MAX_NEIGHBOURS: u32 = 16u;
+
 struct Boids{
-    boids:[[stride(32)]]array<Boid>;
+    boids:[[stride(48)]]array<Boid>;
+};
+
+struct FlowField{
+    cells:[[stride(8)]]array<vec2<f32>>;
+};
+
+struct Path{
+    start:u32;
+    count:u32;
+    species:u32;
+    looped:u32;
+    arrivalRadius:f32;
+};
+
+struct Paths{
+    paths:[[stride(20)]]array<Path>;
+};
+
+struct Waypoints{
+    points:[[stride(8)]]array<vec2<f32>>;
+};
+
+// Per boid state kept across steps, not double buffered as each boid only touches its own
+struct BoidState{
+    waypoint:u32;
+    neighbours:u32;
+};
+
+struct BoidStates{
+    states:[[stride(8)]]array<BoidState>;
 };
 
+// Steering components written per boid for the debug overlay :
+// separation, alignement, cohesion, center pull and the resulting velocity
+struct DebugVectors{
+    vectors:[[stride(16)]]array<vec4<f32>>;
+};
+
+// Must match DEBUG_VECTORS in application.rs
+let DEBUG_VECTORS: u32 = 5u;
+
+// Must match ALL_SPECIES in waypoints.rs
+let ALL_SPECIES: u32 = 4294967295u;
+
 [[group(0), binding(0)]]
 var<uniform> params: Params;
 [[group(0), binding(1)]]
 var<storage> in: Boids;
 [[group(0), binding(2)]]
 var<storage, read_write> out: Boids;
+[[group(0), binding(3)]]
+var<storage> flow: FlowField;
+[[group(0), binding(4)]]
+var<storage> paths: Paths;
+[[group(0), binding(5)]]
+var<storage> waypoints: Waypoints;
+[[group(0), binding(6)]]
+var<storage, read_write> states: BoidStates;
+[[group(0), binding(7)]]
+var<storage, read_write> debugOut: DebugVectors;
+
+// Bilinear sampling of the flow field, null outside of it
+fn sampleFlow(pos: vec2<f32>) -> vec2<f32> {
+    let cell = (pos - vec2<f32>(params.flowOriginX, params.flowOriginY)) / params.flowCellSize;
+    if (cell.x < 0.0 || cell.y < 0.0 || cell.x > f32(params.flowWidth - 1u) || cell.y > f32(params.flowHeight - 1u)) {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let x0 = u32(cell.x);
+    let y0 = u32(cell.y);
+    let x1 = min(x0 + 1u, params.flowWidth - 1u);
+    let y1 = min(y0 + 1u, params.flowHeight - 1u);
+    let t = fract(cell);
+
+    let c00 = flow.cells[y0 * params.flowWidth + x0];
+    let c10 = flow.cells[y0 * params.flowWidth + x1];
+    let c01 = flow.cells[y1 * params.flowWidth + x0];
+    let c11 = flow.cells[y1 * params.flowWidth + x1];
+    let bottom = c00 + (c10 - c00) * t.x;
+    let top = c01 + (c11 - c01) * t.x;
+    return bottom + (top - bottom) * t.y;
+}
 
 [[stage(compute), workgroup_size(64)]]
 fn step([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>){
@@ -40,7 +138,7 @@ fn step([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>){
 
     var vPos: vec2<f32> = in.boids[index].position;
     var vVel: vec2<f32> = in.boids[index].speed;
-    var vColor: vec3<f32> =  in.boids[index].color;
+    var vColor: vec3<f32> =  in.boids[index].color.xyz;
 
     var sepSum: vec2<f32> = vec2<f32>(0.0, 0.0);
     var sepCount: u32 = 0u;
@@ -49,11 +147,61 @@ fn step([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>){
     var cohSum: vec2<f32> = vec2<f32>(0.0, 0.0);
     var cohCount: u32 = 0u;
 
-    var i:u32 = 0u;
+    let topological = params.topological != 0u;
+
+    // Gathering the k nearest boids sorted by distance for the topological neighbourhood
+    var nearest: array<u32, MAX_NEIGHBOURS>;
+    var nearestDist: array<f32, MAX_NEIGHBOURS>;
+    var nearestCount: u32 = 0u;
+    let k = min(max(max(params.separationK, params.alignementK), params.cohesionK), MAX_NEIGHBOURS);
+    if (topological && k > 0u) {
+        var i:u32 = 0u;
+        loop {
+            if (i >= total) {
+                break;
+            }
+            if (index == i) {
+                continue;
+            }
+
+            let dist = distance(in.boids[i].position, vPos);
+            if (nearestCount < k || dist < nearestDist[nearestCount - 1u]) {
+                // Insertion sort, dropping the furthest boid when the list is full
+                var j: u32 = min(nearestCount, k - 1u);
+                loop {
+                    if (j == 0u || nearestDist[j - 1u] <= dist) {
+                        break;
+                    }
+                    nearestDist[j] = nearestDist[j - 1u];
+                    nearest[j] = nearest[j - 1u];
+                    j = j - 1u;
+                }
+                nearestDist[j] = dist;
+                nearest[j] = i;
+                nearestCount = min(nearestCount + 1u, k);
+            }
+
+            continuing {
+              i = i + 1u;
+            }
+        }
+    }
+
+    var count: u32 = total;
+    if (topological) {
+        count = nearestCount;
+    }
+
+    var neighbours: u32 = 0u;
+    var n:u32 = 0u;
     loop {
-        if (i >= total) {
+        if (n >= count) {
             break;
         }
+        var i: u32 = n;
+        if (topological) {
+            i = nearest[n];
+        }
         if (index == i) {
             continue;
         }
@@ -62,45 +210,125 @@ fn step([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>){
         let oVel = in.boids[i].speed;
         let dist = distance(oPos,vPos);
 
-        if(dist < params.separationReach){
+        var inSeparation = dist < params.separationReach;
+        var inAlignement = dist < params.alignementReach;
+        var inCohesion = dist < params.cohesionReach;
+        if (topological) {
+            inSeparation = n < params.separationK;
+            inAlignement = n < params.alignementK;
+            inCohesion = n < params.cohesionK;
+        }
+
+        if(inSeparation || inAlignement || inCohesion){
+            neighbours = neighbours + 1u;
+        }
+        if(inSeparation){
             sepSum = sepSum + normalize(vPos - oPos) / ( dist * dist);
             sepCount = sepCount + 1u;
         }
-        if(dist < params.alignementReach){
+        if(inAlignement){
             aliSum = aliSum + oVel;
             aliCount = aliCount + 1u;
         }
-        if(dist < params.cohesionReach){
+        if(inCohesion){
             cohSum = cohSum + oPos;
             cohCount = cohCount + 1u;
         }
 
         continuing {
-          i = i + 1u;
+          n = n + 1u;
         }
     }
 
-    let inertia = 20.;
-
-    vVel = vVel * inertia;
-
+    // Steering forces of each rule
+    var separation: vec2<f32> = vec2<f32>(0.0, 0.0);
+    var alignement: vec2<f32> = vec2<f32>(0.0, 0.0);
+    var cohesion: vec2<f32> = vec2<f32>(0.0, 0.0);
     if(sepCount>0u){
-        vVel = vVel + sepSum * params.separationScale * params.deltaT;
+        separation = sepSum * params.separationScale;
     }
     if(aliCount>0u){
-        vVel = vVel + aliSum * params.alignementScale * params.deltaT;
+        alignement = aliSum * params.alignementScale;
     }
     if(cohCount>0u){
         let centerOfGrav = cohSum / f32(cohCount);
-        vVel = vVel + (- vPos + centerOfGrav)  * params.cohesionScale * params.deltaT;
+        cohesion = (- vPos + centerOfGrav)  * params.cohesionScale;
     }
     let distance_center = length(vPos);
-    vVel = vVel - vPos * distance_center * params.centerAttraction * params.deltaT;
+    let centerPull = -vPos * distance_center * params.centerAttraction;
+    var steering = separation + alignement + cohesion + centerPull;
 
-    vVel = normalize(vVel) * clamp(length(vVel), 0.0, 1.0);
+    // Heading to the current waypoint of the first path meant for this boid's species
+    let species = in.boids[index].species;
+    var p: u32 = 0u;
+    loop {
+        if (p >= params.pathCount) {
+            break;
+        }
+        let path = paths.paths[p];
+        if (path.species == ALL_SPECIES || path.species == species) {
+            var waypoint = min(states.states[index].waypoint, path.count - 1u);
+            if (distance(waypoints.points[path.start + waypoint], vPos) < path.arrivalRadius) {
+                if (waypoint + 1u < path.count) {
+                    waypoint = waypoint + 1u;
+                } else if (path.looped != 0u) {
+                    waypoint = 0u;
+                }
+                states.states[index].waypoint = waypoint;
+            }
+
+            let toGoal = waypoints.points[path.start + waypoint] - vPos;
+            let goalDist = length(toGoal);
+            if (goalDist > 0.00001) {
+                var desiredSpeed = params.maxSpeed;
+                // Slowing down when arriving at the end of the path
+                if (waypoint + 1u == path.count && path.looped == 0u) {
+                    desiredSpeed = desiredSpeed * min(goalDist / path.arrivalRadius, 1.0);
+                }
+                steering = steering + (toGoal / goalDist * desiredSpeed - vVel) * params.goalScale;
+            }
+            break;
+        }
+
+        continuing {
+          p = p + 1u;
+        }
+    }
+
+    // Limiting the steering force and applying it as an acceleration
+    let force = length(steering);
+    if (force > params.maxSteeringForce) {
+        steering = steering / force * params.maxSteeringForce;
+    }
+    // The flow field and the mouse push the boid regardless of its steering limit
+    var drift = sampleFlow(vPos) * params.flowScale;
+    let toMouse = vec2<f32>(params.mouseX, params.mouseY) - vPos;
+    let mouseDist = length(toMouse);
+    if (params.mouseForce != 0.0 && mouseDist < params.mouseRadius && mouseDist > 0.00001) {
+        drift = drift + toMouse / mouseDist * params.mouseForce * (1.0 - mouseDist / params.mouseRadius);
+    }
+    vVel = vVel + (steering + drift) / params.inertia * params.deltaT;
+
+    // Keeping the speed within the limits, a stalled boid heads up at the minimum speed
+    let speed = length(vVel);
+    if (speed > 0.00001) {
+        vVel = vVel / speed * clamp(speed, params.minSpeed, params.maxSpeed);
+    } else {
+        vVel = vec2<f32>(0.0, params.minSpeed);
+    }
 
     vPos = vPos + vVel * params.deltaT;
 
     out.boids[index].position = vPos;
     out.boids[index].speed = vVel;
+    states.states[index].neighbours = neighbours;
+
+    if (params.debugVectors != 0u) {
+        let base = index * DEBUG_VECTORS;
+        debugOut.vectors[base] = vec4<f32>(separation, 0.0, 0.0);
+        debugOut.vectors[base + 1u] = vec4<f32>(alignement, 0.0, 0.0);
+        debugOut.vectors[base + 2u] = vec4<f32>(cohesion, 0.0, 0.0);
+        debugOut.vectors[base + 3u] = vec4<f32>(centerPull, 0.0, 0.0);
+        debugOut.vectors[base + 4u] = vec4<f32>(vVel, 0.0, 0.0);
+    }
 }
\ No newline at end of file
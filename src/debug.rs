@@ -0,0 +1,191 @@
+use wgpu::{Device, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferSize, PipelineLayoutDescriptor, ShaderModuleDescriptor};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use bytemuck::{Pod, Zeroable};
+use crate::application::{Dimensions, RenderParams, DEBUG_VECTORS};
+use crate::boid::{Boid, Boid3};
+use crate::renderer::{draw_shader_source, DEPTH_FORMAT};
+use crate::simulation::BoidSimulation;
+
+// Must match DEBUG_CIRCLE_SEGMENTS in debug_draw.wgsl
+const DEBUG_CIRCLE_SEGMENTS: u32 = 32;
+// Separation, alignement and cohesion reaches
+const DEBUG_CIRCLES: u32 = 3;
+// Size of the steering vectors of a boid
+pub const DEBUG_VECTOR_SIZE: u64 = 16 * DEBUG_VECTORS as u64;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct DebugUniforms {
+    separation_reach: f32,
+    alignement_reach: f32,
+    cohesion_reach: f32,
+    vector_scale: f32,
+    floats_per_boid: u32,
+    all: u32,
+}
+
+// Debug overlay drawing the perception radii of the boids and the steering vectors the
+// compute pass writes to the debug vectors buffer of the simulation, for the selected boids or all of them
+pub struct DebugOverlay {
+    render_pipeline: wgpu::RenderPipeline,
+    bind_groups: Vec<wgpu::BindGroup>,
+    params_buffer: wgpu::Buffer,
+    uniform: DebugUniforms,
+    selection_count: u32,
+    boid_count: u32,
+}
+
+impl DebugOverlay {
+    pub fn new(
+        device: &Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        simulation: &BoidSimulation,
+        render_params: &RenderParams,
+    ) -> Self {
+        let simulation_params = simulation.params();
+        let boid_count = simulation.boid_count();
+        let dimensions = simulation_params.dimensions;
+        let boid_size = match dimensions {
+            Dimensions::Two => std::mem::size_of::<Boid>(),
+            Dimensions::Three => std::mem::size_of::<Boid3>(),
+        };
+        let uniform = DebugUniforms {
+            separation_reach: simulation_params.separation_reach,
+            alignement_reach: simulation_params.alignement_reach,
+            cohesion_reach: simulation_params.cohesion_reach,
+            vector_scale: render_params.debug_vector_scale,
+            floats_per_boid: (boid_size / 4) as u32,
+            all: 0,
+        };
+        let params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Debug params buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let mut selection: Vec<u32> = render_params.debug_selection.iter().copied().filter(|&i| i < boid_count).collect();
+        let selection_count = selection.len() as u32;
+        // Buffers can't be empty
+        if selection.is_empty() {
+            selection.push(0);
+        }
+        let selection_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Debug selection buffer"),
+            contents: bytemuck::cast_slice(&selection),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Debug Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(std::mem::size_of::<DebugUniforms>() as u64),
+                    },
+                    count: None,
+                },
+                storage_entry(1),
+                storage_entry(2),
+                storage_entry(3),
+            ],
+        });
+        let bind_groups = simulation.boid_buffers().iter().enumerate().map(|(i, boid_buffer)| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&*format!("Debug binding group {}", i)),
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: boid_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: simulation.debug_vectors_buffer().as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 3, resource: selection_buffer.as_entire_binding() },
+                ],
+            })
+        }).collect();
+
+        // Drawn by the vs_debug entry point of the boid shader to share its projection
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("RenderDebug"),
+            source: wgpu::ShaderSource::Wgsl(draw_shader_source(dimensions).into()),
+        });
+        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Debug Render Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_debug",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // Drawn last, over everything
+            depth_stencil: match dimensions {
+                Dimensions::Two => None,
+                Dimensions::Three => Some(wgpu::DepthStencilState {
+                    format: DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+            },
+            multisample: wgpu::MultisampleState {
+                count: render_params.msaa_samples,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_debug",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            render_pipeline,
+            bind_groups,
+            params_buffer,
+            uniform,
+            selection_count,
+            boid_count,
+        }
+    }
+
+    // Switches between the selected boids and the whole flock
+    pub fn show_all(&mut self, queue: &wgpu::Queue, all: bool) {
+        self.uniform.all = all as u32;
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Expects the camera to be bound to group 0
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, buffer_index: usize) {
+        let instances = if self.uniform.all != 0 { self.boid_count } else { self.selection_count };
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(1, &self.bind_groups[buffer_index], &[]);
+        render_pass.draw(0..2 * (DEBUG_CIRCLES * DEBUG_CIRCLE_SEGMENTS + DEBUG_VECTORS), 0..instances);
+    }
+}
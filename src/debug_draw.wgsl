@@ -0,0 +1,92 @@
+// Debug overlay pass shared by draw.wgsl and draw3.wgsl, appended to them
+
+struct DebugParams {
+    separationReach: f32;
+    alignementReach: f32;
+    cohesionReach: f32;
+    vectorScale: f32;
+    floatsPerBoid: u32;
+    all: u32;
+};
+
+struct DebugFloats {
+    values: [[stride(4)]] array<f32>;
+};
+
+struct DebugVectors {
+    vectors: [[stride(16)]] array<vec4<f32>>;
+};
+
+struct DebugSelection {
+    boids: [[stride(4)]] array<u32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> debug_params: DebugParams;
+[[group(1), binding(1)]]
+var<storage> debug_boids: DebugFloats;
+[[group(1), binding(2)]]
+var<storage> debug_vectors: DebugVectors;
+[[group(1), binding(3)]]
+var<storage> debug_selection: DebugSelection;
+
+// Must match DEBUG_CIRCLE_SEGMENTS in debug.rs and DEBUG_VECTORS in application.rs
+let DEBUG_CIRCLE_SEGMENTS: u32 = 32u;
+let DEBUG_VECTORS: u32 = 5u;
+
+struct DebugOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+};
+
+// Line list of the separation, alignement and cohesion circles followed by the separation,
+// alignement, cohesion and center pull forces and the velocity of a boid
+[[stage(vertex)]]
+fn vs_debug([[builtin(vertex_index)]] vertex: u32, [[builtin(instance_index)]] instance: u32) -> DebugOutput {
+    var colors = array<vec3<f32>, 5>(
+        vec3<f32>(1.0, 0.2, 0.2), vec3<f32>(0.2, 1.0, 0.2), vec3<f32>(0.3, 0.5, 1.0),
+        vec3<f32>(1.0, 1.0, 0.2), vec3<f32>(1.0, 1.0, 1.0)
+    );
+    var boid = instance;
+    if (debug_params.all == 0u) {
+        boid = debug_selection.boids[instance];
+    }
+    let base = boid * debug_params.floatsPerBoid;
+    var pos = vec3<f32>(debug_boids.values[base], debug_boids.values[base + 1u], 0.0);
+    if (DIMENSIONS == 3u) {
+        pos.z = debug_boids.values[base + 2u];
+    }
+
+    var offset: vec3<f32>;
+    var out: DebugOutput;
+    let circleVertices = 2u * DEBUG_CIRCLE_SEGMENTS;
+    if (vertex < 3u * circleVertices) {
+        let circle = vertex / circleVertices;
+        let point = (vertex % circleVertices) / 2u + vertex % 2u;
+        let angle = f32(point) / f32(DEBUG_CIRCLE_SEGMENTS) * 6.28318530718;
+        var radius = debug_params.separationReach;
+        if (circle == 1u) {
+            radius = debug_params.alignementReach;
+        } else if (circle == 2u) {
+            radius = debug_params.cohesionReach;
+        }
+        offset = vec3<f32>(cos(angle), sin(angle), 0.0) * radius;
+        out.color = vec4<f32>(colors[circle], 0.4);
+    } else {
+        let component = (vertex - 3u * circleVertices) / 2u;
+        // The velocity is drawn as is, the forces being much larger
+        var scale = debug_params.vectorScale;
+        if (component == 4u) {
+            scale = 1.0;
+        }
+        offset = debug_vectors.vectors[boid * DEBUG_VECTORS + component].xyz * scale * f32(vertex % 2u);
+        out.color = vec4<f32>(colors[component], 1.0);
+    }
+    out.clip_position = project(pos + offset);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_debug(in: DebugOutput) -> [[location(0)]] vec4<f32> {
+    return in.color;
+}
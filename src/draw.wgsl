@@ -7,36 +7,74 @@ struct CameraUniform {
 
 [[group(0), binding(0)]]
 var<uniform> camera: CameraUniform;
+[[group(0), binding(2)]]
+var sprite_texture: texture_2d<f32>;
+[[group(0), binding(3)]]
+var sprite_sampler: sampler;
+
+// Dimension count and projection used by the passes appended to this shader
+let DIMENSIONS: u32 = 2u;
+
+fn project(position: vec3<f32>) -> vec4<f32> {
+    return vec4<f32>((position.xy - camera.origin) * camera.scaling, 0.0, 1.0);
+}
 
 
 struct VertexInput {
     [[location(0)]] boid_pos:vec2<f32>;
     [[location(1)]] boid_vel:vec2<f32>;
-    [[location(2)]] boid_color:vec3<f32>;
+    [[location(2)]] boid_color:vec4<f32>;
     [[location(3)]] position:vec2<f32>;
+    [[location(4)]] uv:vec2<f32>;
+    [[location(5)]] species:u32;
+    [[location(6)]] neighbours:u32;
+    [[location(7)]] shape:u32;
 };
 
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
-    [[location(0)]] color:vec3<f32>;
+    [[location(0)]] color:vec4<f32>;
+    [[location(1)]] uv:vec2<f32>;
 };
 
 [[stage(vertex)]]
 fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    if (in.species % render.shapeCount != in.shape) {
+        // Vertex of another species' shape, sent out of the clip space
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        out.color = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+        out.uv = in.uv;
+        return out;
+    }
     let sqrlen = dot(in.boid_vel, in.boid_vel);
-    let angle = -atan2(in.boid_vel.x / sqrt(sqrlen), in.boid_vel.y / sqrt(sqrlen));
+    // A boid without velocity keeps pointing up instead of getting a NaN heading
+    var angle: f32 = 0.0;
+    if (sqrlen > 0.0) {
+        angle = -atan2(in.boid_vel.x / sqrt(sqrlen), in.boid_vel.y / sqrt(sqrlen));
+    }
+    let position = in.position * render.boidSize;
     let v_pos = vec2<f32>(
-        in.position.x * cos(angle) - in.position.y * sin(angle),
-        in.position.x * sin(angle) + in.position.y * cos(angle)
+        position.x * cos(angle) - position.y * sin(angle),
+        position.x * sin(angle) + position.y * cos(angle)
     );
-    var out: VertexOutput;
     out.clip_position = vec4<f32>((v_pos + in.boid_pos - camera.origin) * camera.scaling, 0.0, 1.0);
-    out.color = in.boid_color;
+    out.color = vec4<f32>(boidColor(in.boid_color.xyz, vec3<f32>(in.boid_vel, 0.0), in.neighbours, in.species), in.boid_color.w);
+    out.uv = in.uv;
     return out;
 }
 
 // Fragment shader
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
+    // Sampled before branching as sampling needs uniform control flow
+    let texel = textureSample(sprite_texture, sprite_sampler, max(in.uv, vec2<f32>(0.0)));
+    if (in.uv.x < 0.0) {
+        return in.color;
+    }
+    let color = in.color * texel;
+    if (color.a < 0.01) {
+        discard;
+    }
+    return color;
 }
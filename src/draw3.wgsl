@@ -0,0 +1,62 @@
+// Vertex shader
+
+struct CameraUniform {
+    view_proj:mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+// Dimension count and projection used by the passes appended to this shader
+let DIMENSIONS: u32 = 3u;
+
+fn project(position: vec3<f32>) -> vec4<f32> {
+    return camera.view_proj * vec4<f32>(position, 1.0);
+}
+
+
+struct VertexInput {
+    [[location(0)]] boid_pos:vec3<f32>;
+    [[location(1)]] boid_vel:vec3<f32>;
+    [[location(2)]] boid_color:vec4<f32>;
+    [[location(3)]] position:vec3<f32>;
+    [[location(4)]] normal:vec3<f32>;
+    [[location(5)]] species:u32;
+    [[location(6)]] neighbours:u32;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color:vec4<f32>;
+    [[location(1)]] normal:vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(in: VertexInput) -> VertexOutput {
+    // Orienting the mesh's +y axis along the velocity
+    var forward = vec3<f32>(0.0, 1.0, 0.0);
+    if (dot(in.boid_vel, in.boid_vel) > 0.0) {
+        forward = normalize(in.boid_vel);
+    }
+    var reference = vec3<f32>(0.0, 0.0, 1.0);
+    if (abs(forward.z) > 0.99) {
+        reference = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let right = normalize(cross(forward, reference));
+    let up = cross(right, forward);
+    let rotation = mat3x3<f32>(right, forward, up);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(rotation * in.position * render.boidSize + in.boid_pos, 1.0);
+    out.color = vec4<f32>(boidColor(in.boid_color.xyz, in.boid_vel, in.neighbours, in.species), in.boid_color.w);
+    out.normal = rotation * in.normal;
+    return out;
+}
+
+// Fragment shader
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let light = normalize(vec3<f32>(0.3, 0.5, 1.0));
+    let diffuse = max(dot(normalize(in.normal), light), 0.0);
+    return vec4<f32>(in.color.xyz * (0.3 + 0.7 * diffuse), in.color.w);
+}
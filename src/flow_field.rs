@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::path::Path;
+use anyhow::{bail, Context};
+
+/// A grid of 2D vectors covering a rectangle of the world, sampled by the compute shader
+/// at each boid position and added to its steering as a drift force.
+/// Cells are stored row by row starting from the bottom left corner (origin).
+#[derive(Clone, Debug)]
+pub struct FlowField {
+    /// World position of the bottom left cell
+    pub origin: [f32; 2],
+    /// Side of a cell in world units
+    pub cell_size: f32,
+    /// Number of cells per row
+    pub width: u32,
+    /// Number of rows
+    pub height: u32,
+    /// Drift of each cell, width * height of them
+    pub cells: Vec<[f32; 2]>,
+}
+
+impl FlowField {
+    /// A field with no effect
+    pub fn none() -> Self {
+        Self { origin: [0.0, 0.0], cell_size: 1.0, width: 1, height: 1, cells: vec![[0.0, 0.0]] }
+    }
+
+    /// A zeroed field of width x height cells whose bottom left corner is at origin
+    pub fn new(origin: [f32; 2], cell_size: f32, width: u32, height: u32) -> Self {
+        Self { origin, cell_size, width, height, cells: vec![[0.0, 0.0]; (width * height) as usize] }
+    }
+
+    fn fill(mut self, f: impl Fn([f32; 2]) -> [f32; 2]) -> Self {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pos = [
+                    self.origin[0] + x as f32 * self.cell_size,
+                    self.origin[1] + y as f32 * self.cell_size,
+                ];
+                self.cells[(y * self.width + x) as usize] = f(pos);
+            }
+        }
+        self
+    }
+
+    /// The same wind everywhere
+    pub fn uniform(self, wind: [f32; 2]) -> Self {
+        self.fill(|_| wind)
+    }
+
+    /// A counter clockwise rotation around center, fading out with the distance to it
+    pub fn vortex(self, center: [f32; 2], radius: f32) -> Self {
+        self.fill(|pos| {
+            let d = [pos[0] - center[0], pos[1] - center[1]];
+            let dist = (d[0] * d[0] + d[1] * d[1]).sqrt();
+            if dist == 0.0 {
+                return [0.0, 0.0];
+            }
+            let strength = (dist / radius) * (1.0 - dist / radius).exp();
+            [-d[1] / dist * strength, d[0] / dist * strength]
+        })
+    }
+
+    /// A turbulent but divergence free field : the curl of a smooth noise
+    pub fn curl_noise(self, frequency: f32, seed: u32) -> Self {
+        let eps = 0.01 / frequency;
+        let potential = |x: f32, y: f32| value_noise(x * frequency, y * frequency, seed);
+        let field = self.fill(|[x, y]| [
+            (potential(x, y + eps) - potential(x, y - eps)) / (2.0 * eps),
+            -(potential(x + eps, y) - potential(x - eps, y)) / (2.0 * eps),
+        ]);
+        field.normalized()
+    }
+
+    /// Loads a field from a PNG, the red and green channels mapping [0, 255] to [-1, 1].
+    /// The image covers width x height cells, its top row being the highest one in the world
+    pub fn from_png(path: impl AsRef<Path>, origin: [f32; 2], cell_size: f32) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("could not open flow field {}", path.display()))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().with_context(|| format!("invalid PNG {}", path.display()))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let channels = match info.color_type {
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            color_type => bail!("flow field {} must be RGB or RGBA, not {:?}", path.display(), color_type),
+        };
+
+        let mut field = Self::new(origin, cell_size, info.width, info.height);
+        for (row, line) in buffer.chunks(info.line_size).take(info.height as usize).enumerate() {
+            let y = info.height as usize - 1 - row;
+            for x in 0..info.width as usize {
+                let pixel = &line[x * channels..];
+                field.cells[y * info.width as usize + x] = [
+                    pixel[0] as f32 / 255.0 * 2.0 - 1.0,
+                    pixel[1] as f32 / 255.0 * 2.0 - 1.0,
+                ];
+            }
+        }
+        Ok(field)
+    }
+
+    // Scales the field so that its strongest vector has a length of 1
+    fn normalized(mut self) -> Self {
+        let max = self.cells.iter()
+            .map(|c| (c[0] * c[0] + c[1] * c[1]).sqrt())
+            .fold(0.0, f32::max);
+        if max > 0.0 {
+            for c in self.cells.iter_mut() {
+                *c = [c[0] / max, c[1] / max];
+            }
+        }
+        self
+    }
+}
+
+fn hash(x: i32, y: i32, seed: u32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ seed.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    h as f32 / u32::MAX as f32
+}
+
+fn value_noise(x: f32, y: f32, seed: u32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+    let (x0, y0) = (x0 as i32, y0 as i32);
+    let bottom = hash(x0, y0, seed) * (1.0 - tx) + hash(x0 + 1, y0, seed) * tx;
+    let top = hash(x0, y0 + 1, seed) * (1.0 - tx) + hash(x0 + 1, y0 + 1, seed) * tx;
+    bottom * (1.0 - ty) + top * ty
+}
@@ -0,0 +1,251 @@
+use wgpu::{Device, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferSize, PipelineLayoutDescriptor, ShaderModuleDescriptor};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use bytemuck::{Pod, Zeroable};
+use crate::application::{Dimensions, RenderParams};
+use crate::renderer::{draw_shader_source, DEPTH_FORMAT};
+use crate::boid::{Boid, Boid3};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct HeatmapUniforms {
+    origin_x: f32,
+    origin_y: f32,
+    cell_size: f32,
+    width: u32,
+    height: u32,
+    floats_per_boid: u32,
+    max_density: f32,
+    opacity: f32,
+}
+
+// Density heatmap : the boids are counted in a low resolution grid centered on the origin,
+// which is blurred then drawn with the colormap. 3D boids are projected on the z = 0 plane
+pub struct Heatmap {
+    clear_pipeline: wgpu::ComputePipeline,
+    splat_pipeline: wgpu::ComputePipeline,
+    blur_pipeline: wgpu::ComputePipeline,
+    compute_bind_groups: Vec<wgpu::BindGroup>,
+    render_pipeline: wgpu::RenderPipeline,
+    render_bind_group: wgpu::BindGroup,
+    cell_workgroup_count: u32,
+    boid_workgroup_count: u32,
+}
+
+impl Heatmap {
+    pub fn new(
+        device: &Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        dimensions: Dimensions,
+        boid_buffers: &[wgpu::Buffer],
+        boid_count: u32,
+        render_params: &RenderParams,
+    ) -> Self {
+        let resolution = render_params.heatmap_resolution.max(1);
+        let cell_count = resolution * resolution;
+        let boid_size = match dimensions {
+            Dimensions::Two => std::mem::size_of::<Boid>(),
+            Dimensions::Three => std::mem::size_of::<Boid3>(),
+        };
+        let cell_size = 2.0 * render_params.heatmap_extent / resolution as f32;
+        let uniform = HeatmapUniforms {
+            origin_x: -render_params.heatmap_extent,
+            origin_y: -render_params.heatmap_extent,
+            cell_size,
+            width: resolution,
+            height: resolution,
+            floats_per_boid: (boid_size / 4) as u32,
+            // Boids per cell
+            max_density: render_params.heatmap_max.max(f32::EPSILON),
+            opacity: render_params.heatmap_opacity,
+        };
+
+        let params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Heatmap params buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Heatmap count buffer"),
+            size: 4 * cell_count as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let density_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Heatmap density buffer"),
+            size: 4 * cell_count as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        // Splatting and blurring
+        let storage_entry = |binding, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let compute_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Heatmap Compute Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(std::mem::size_of::<HeatmapUniforms>() as u64),
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+                storage_entry(3, false),
+            ],
+        });
+        let compute_bind_groups = boid_buffers.iter().enumerate().map(|(i, boid_buffer)| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&*format!("Heatmap compute binding group {}", i)),
+                layout: &compute_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: boid_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: count_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 3, resource: density_buffer.as_entire_binding() },
+                ],
+            })
+        }).collect();
+        let compute_shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("Heatmap"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("heatmap.wgsl").into()),
+        });
+        let compute_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Heatmap Compute Pipeline Layout"),
+            bind_group_layouts: &[&compute_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let compute_pipeline = |entry_point| device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Heatmap Compute Pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &compute_shader,
+            entry_point,
+        });
+
+        // Drawing, done by the vs_heatmap entry point of the boid shader to share its projection
+        let render_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Heatmap Render Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(std::mem::size_of::<HeatmapUniforms>() as u64),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Heatmap render binding group"),
+            layout: &render_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: density_buffer.as_entire_binding() },
+            ],
+        });
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("RenderHeatmap"),
+            source: wgpu::ShaderSource::Wgsl(draw_shader_source(dimensions).into()),
+        });
+        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Heatmap Render Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &render_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Heatmap Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_heatmap",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            // Drawn first, behind everything
+            depth_stencil: match dimensions {
+                Dimensions::Two => None,
+                Dimensions::Three => Some(wgpu::DepthStencilState {
+                    format: DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+            },
+            multisample: wgpu::MultisampleState {
+                count: render_params.msaa_samples,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_heatmap",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            clear_pipeline: compute_pipeline("clear"),
+            splat_pipeline: compute_pipeline("splat"),
+            blur_pipeline: compute_pipeline("blur"),
+            compute_bind_groups,
+            render_pipeline,
+            render_bind_group,
+            cell_workgroup_count: ((cell_count as f32) / 64_f32).ceil() as u32,
+            boid_workgroup_count: ((boid_count as f32) / 64_f32).ceil() as u32,
+        }
+    }
+
+    // Computes the density of the boids held by boid_buffers[buffer_index]
+    pub fn update(&self, encoder: &mut wgpu::CommandEncoder, buffer_index: usize) {
+        // One pass per stage so that each one sees the writes of the previous one
+        let stages = [
+            (&self.clear_pipeline, self.cell_workgroup_count),
+            (&self.splat_pipeline, self.boid_workgroup_count),
+            (&self.blur_pipeline, self.cell_workgroup_count),
+        ];
+        for (pipeline, workgroup_count) in stages {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("Heatmap Pass") });
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, &self.compute_bind_groups[buffer_index], &[]);
+            compute_pass.dispatch(workgroup_count, 1, 1);
+        }
+    }
+
+    // Expects the camera to be bound to group 0
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(1, &self.render_bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
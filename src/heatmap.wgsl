@@ -0,0 +1,103 @@
+// Splats the boid positions into a grid of counts then blurs it into the density drawn by
+// fs_heatmap in heatmap_draw.wgsl
+
+struct HeatmapParams {
+    originX: f32;
+    originY: f32;
+    cellSize: f32;
+    width: u32;
+    height: u32;
+    floatsPerBoid: u32; // 12 for 2D boids, 16 for 3D boids
+    maxDensity: f32;
+    opacity: f32;
+};
+
+// The boids seen as raw floats so that both 2D and 3D boids can be splatted, the position coming first
+struct Floats {
+    values: [[stride(4)]] array<f32>;
+};
+
+struct Counts {
+    cells: [[stride(4)]] array<atomic<u32>>;
+};
+
+struct Density {
+    cells: [[stride(4)]] array<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: HeatmapParams;
+[[group(0), binding(1)]]
+var<storage> boids: Floats;
+[[group(0), binding(2)]]
+var<storage, read_write> counts: Counts;
+[[group(0), binding(3)]]
+var<storage, read_write> density: Density;
+
+[[stage(compute), workgroup_size(64)]]
+fn clear([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>) {
+    let index = global_invocation_id.x;
+    if (index >= params.width * params.height) {
+        return;
+    }
+    atomicStore(&counts.cells[index], 0u);
+}
+
+// The 3D boids are projected on the z = 0 plane
+[[stage(compute), workgroup_size(64)]]
+fn splat([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>) {
+    let total = arrayLength(&boids.values) / params.floatsPerBoid;
+    let index = global_invocation_id.x;
+    if (index >= total) {
+        return;
+    }
+
+    let base = index * params.floatsPerBoid;
+    let pos = vec2<f32>(boids.values[base], boids.values[base + 1u]);
+    let cell = (pos - vec2<f32>(params.originX, params.originY)) / params.cellSize;
+    if (cell.x < 0.0 || cell.y < 0.0 || cell.x >= f32(params.width) || cell.y >= f32(params.height)) {
+        return;
+    }
+    atomicAdd(&counts.cells[u32(cell.y) * params.width + u32(cell.x)], 1u);
+}
+
+// 5x5 binomial blur, the cells outside of the grid counting as empty
+[[stage(compute), workgroup_size(64)]]
+fn blur([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>) {
+    let index = global_invocation_id.x;
+    if (index >= params.width * params.height) {
+        return;
+    }
+
+    var weights = array<f32, 5>(1.0, 4.0, 6.0, 4.0, 1.0);
+    let x = i32(index % params.width);
+    let y = i32(index / params.width);
+    var sum: f32 = 0.0;
+    var dy: i32 = -2;
+    loop {
+        if (dy > 2) {
+            break;
+        }
+        var dx: i32 = -2;
+        loop {
+            if (dx > 2) {
+                break;
+            }
+            let cx = x + dx;
+            let cy = y + dy;
+            if (cx >= 0 && cy >= 0 && cx < i32(params.width) && cy < i32(params.height)) {
+                let count = atomicLoad(&counts.cells[u32(cy) * params.width + u32(cx)]);
+                sum = sum + f32(count) * weights[dx + 2] * weights[dy + 2];
+            }
+
+            continuing {
+              dx = dx + 1;
+            }
+        }
+
+        continuing {
+          dy = dy + 1;
+        }
+    }
+    density.cells[index] = sum / 256.0;
+}
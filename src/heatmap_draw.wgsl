@@ -0,0 +1,61 @@
+// Density heatmap pass shared by draw.wgsl and draw3.wgsl, appended to them
+
+struct HeatmapParams {
+    originX: f32;
+    originY: f32;
+    cellSize: f32;
+    width: u32;
+    height: u32;
+    floatsPerBoid: u32;
+    maxDensity: f32;
+    opacity: f32;
+};
+
+struct DensityCells {
+    cells: [[stride(4)]] array<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> heatmap_params: HeatmapParams;
+[[group(1), binding(1)]]
+var<storage> heatmap_density: DensityCells;
+
+struct HeatmapOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] cell: vec2<f32>;
+};
+
+// Quad covering the density grid
+[[stage(vertex)]]
+fn vs_heatmap([[builtin(vertex_index)]] vertex: u32) -> HeatmapOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 0.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 1.0), vec2<f32>(0.0, 1.0)
+    );
+    let size = vec2<f32>(f32(heatmap_params.width), f32(heatmap_params.height));
+    let corner = corners[vertex];
+    let world = vec2<f32>(heatmap_params.originX, heatmap_params.originY) + corner * size * heatmap_params.cellSize;
+    var out: HeatmapOutput;
+    out.clip_position = project(vec3<f32>(world, 0.0));
+    // Cell centers at integer coordinates
+    out.cell = corner * size - 0.5;
+    return out;
+}
+
+fn densityAt(x: i32, y: i32) -> f32 {
+    let cx = u32(clamp(x, 0, i32(heatmap_params.width) - 1));
+    let cy = u32(clamp(y, 0, i32(heatmap_params.height) - 1));
+    return heatmap_density.cells[cy * heatmap_params.width + cx];
+}
+
+[[stage(fragment)]]
+fn fs_heatmap(in: HeatmapOutput) -> [[location(0)]] vec4<f32> {
+    let base = floor(in.cell);
+    let t = in.cell - base;
+    let x = i32(base.x);
+    let y = i32(base.y);
+    let bottom = mix(densityAt(x, y), densityAt(x + 1, y), t.x);
+    let top = mix(densityAt(x, y + 1), densityAt(x + 1, y + 1), t.x);
+    let level = clamp(mix(bottom, top, t.y) / heatmap_params.maxDensity, 0.0, 1.0);
+    return vec4<f32>(colormap(level), level * heatmap_params.opacity);
+}
@@ -0,0 +1,84 @@
+//! GPU boids simulation and renderer built on wgpu.
+//!
+//! The flock is simulated by compute shaders, in 2D or in 3D, and drawn to the surface of a
+//! winit window. [`ApplicationState`] owns both the simulation and the rendering and is driven
+//! by the event loop of the embedding application:
+//!
+//! ```no_run
+//! use boids_web::{ApplicationState, AppParams, SimulationParams, RenderParams};
+//!
+//! async fn run(
+//!     window: &winit::window::Window,
+//!     simulation_params: SimulationParams,
+//!     render_params: RenderParams,
+//!     app_params: AppParams,
+//! ) -> anyhow::Result<()> {
+//!     let mut app = ApplicationState::init(window, simulation_params, render_params, app_params).await?;
+//!     // Once per frame, after giving the window events to app.input
+//!     app.update();
+//!     if let Err(wgpu::SurfaceError::Lost) = app.render() {
+//!         app.resize(app.size());
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! [`SimulationParams`] sets the flocking rules, flow field and paths, [`RenderParams`] how the
+//! boids are colored and shaped and which overlays can be shown, [`AppParams`] what is recorded
+//! and logged along the way. The cameras are moved with the keyboard through
+//! [`ApplicationState::input`].
+//!
+//! Without a window, a [`BoidSimulation`] can be stepped on its own and drawn by a
+//! [`BoidRenderer`] into any texture, both sharing the device and the queue they are given.
+
+#![warn(missing_docs)]
+
+/// The application driving the simulation and the rendering, and their parameters
+pub mod application;
+mod background;
+/// Boids as laid out in the GPU buffers
+pub mod boid;
+/// Cameras of the 2D and 3D modes
+pub mod camera;
+mod debug;
+/// Flow fields drifting the boids
+pub mod flow_field;
+mod heatmap;
+mod mapping;
+/// Collective motion metrics of the flock
+pub mod metrics;
+mod mouse;
+/// GPU timing of the compute and render passes
+pub mod profiler;
+/// Video recording of the frames
+pub mod recorder;
+/// Drawing of the flock and its overlays
+pub mod renderer;
+/// Shapes of the boids
+pub mod shape;
+/// Compute shader simulation of the flock
+pub mod simulation;
+/// Frame time statistics
+pub mod stats;
+mod trail;
+/// Trajectory recording of the boids
+pub mod trajectory;
+/// Paths the boids follow
+pub mod waypoints;
+
+pub use application::{
+    AppParams, ApplicationState, ColorMode, Colormap, DebugView, Dimensions, HeatmapMode, Neighbourhood, RenderParams,
+    SimulationParams,
+};
+pub use boid::{Boid, Boid3};
+pub use camera::{Camera, OrbitCamera};
+pub use flow_field::FlowField;
+pub use metrics::{FlockMetrics, MetricsParams};
+pub use profiler::GpuTimer;
+pub use recorder::{RecordOutput, RecordingParams};
+pub use renderer::BoidRenderer;
+pub use shape::BoidShape;
+pub use simulation::BoidSimulation;
+pub use stats::{Stats, StatsSummary};
+pub use trajectory::{TrajectoryFormat, TrajectoryParams};
+pub use waypoints::WaypointPath;
@@ -1,27 +1,96 @@
-mod application;
-mod boid;
-mod camera;
-// mod camera;
-
+use std::time::Duration;
+use anyhow::Context;
 use winit::event_loop::{EventLoop, ControlFlow};
 use winit::window::Window;
 use winit::event::{Event, WindowEvent, KeyboardInput, ElementState, VirtualKeyCode};
-use crate::application::{ApplicationState, SimulationParams};
+use boids_web::{ApplicationState, AppParams, SimulationParams, RenderParams, Dimensions, ColorMode, Colormap, HeatmapMode, DebugView};
+use boids_web::{BoidShape, FlowField, MetricsParams, RecordOutput, RecordingParams, TrajectoryFormat, TrajectoryParams, WaypointPath};
+
 
+async fn run(event_loop: EventLoop<()>, window:Window) -> anyhow::Result<()>{
+    // --3d switches to 3D boids, a flow field image can be given as argument
+    let dimensions = if std::env::args().any(|arg| arg == "--3d") { Dimensions::Three } else { Dimensions::Two };
+    let flow_field = match std::env::args().skip(1).find(|arg| !arg.starts_with("--")) {
+        Some(path) => FlowField::from_png(path, [-30.0, -30.0], 1.0).unwrap_or_else(|e| {
+            log::warn!("{:?}", e);
+            FlowField::none()
+        }),
+        None => FlowField::none(),
+    };
+    // Each --shape=<shape> gives the shape of the next species
+    let mut shapes: Vec<BoidShape> = std::env::args()
+        .filter_map(|arg| arg.strip_prefix("--shape=").map(BoidShape::parse))
+        .filter_map(|shape| shape.map_err(|e| log::warn!("{:?}", e)).ok())
+        .collect();
+    if shapes.is_empty() {
+        shapes.push(BoidShape::Arrow);
+    }
+    // R records to numbered PNGs, or to an encoder given with --record-pipe=<command>
+    let record_output = match std::env::args().find_map(|arg| arg.strip_prefix("--record-pipe=").map(String::from)) {
+        Some(command) => RecordOutput::Pipe(command),
+        None => RecordOutput::Png("frames".into()),
+    };
+    // --metrics=<file> writes the polarization, milling and clustering of the flock to a CSV file
+    let metrics_output = std::env::args().find_map(|arg| arg.strip_prefix("--metrics=").map(Into::into));
+    // --trajectory=<file> records the boids to a CSV file, or to a binary one when it ends with .bin
+    let trajectory_output: Option<std::path::PathBuf> = std::env::args().find_map(|arg| arg.strip_prefix("--trajectory=").map(Into::into));
+    let trajectory_format = match trajectory_output.as_ref().and_then(|path| path.extension()) {
+        Some(extension) if extension == "bin" => TrajectoryFormat::Binary,
+        _ => TrajectoryFormat::Csv,
+    };
 
-async fn run(event_loop: EventLoop<()>, window:Window){
     // Creating the application
     let mut app = ApplicationState::init(&window, SimulationParams{
-        separation_reach: 4.0,
-        separation_scale: 1.0,
-        alignement_reach: 1.0,
-        alignement_scale: 7.5,
-        cohesion_reach: 4.0,
-        cohesion_scale: 3.0,
-        color_mult: 5.0,
-        step_mult: 1.0,
-        center_attraction: 6.0,
-    }).await;
+        dimensions,
+        flow_field,
+        paths: vec![
+            WaypointPath::new(vec![[-15.0, -15.0], [15.0, -15.0], [15.0, 15.0], [-15.0, 15.0]], 4.0)
+                .looped()
+                .for_species(0),
+            WaypointPath::attractor([0.0, 0.0], 5.0).for_species(1),
+        ],
+        ..SimulationParams::default()
+    }, RenderParams{
+        color_mode: ColorMode::Stored,
+        colormap: Colormap::Viridis,
+        max_density: 20.0,
+        trails: false,
+        trail_length: 64,
+        trail_decay: 2.0,
+        msaa_samples: 4,
+        alpha_blending: true,
+        boid_alpha: 0.7,
+        shapes,
+        boid_size: 0.2,
+        heatmap: HeatmapMode::Off,
+        heatmap_resolution: 128,
+        heatmap_extent: 40.0,
+        heatmap_max: 2.0,
+        heatmap_opacity: 0.8,
+        debug: DebugView::Off,
+        debug_selection: vec![0, 1],
+        debug_vector_scale: 0.1,
+        background: true,
+        world_half_size: 30.0,
+    }, AppParams {
+        recording: RecordingParams {
+            output: record_output,
+            width: 1280,
+            height: 720,
+            fps: 60.0,
+        },
+        stats_log_interval: Duration::from_secs(5),
+        metrics: MetricsParams {
+            output: metrics_output,
+            interval: 30,
+            cluster_radius: 2.0,
+        },
+        trajectory: TrajectoryParams {
+            output: trajectory_output,
+            format: trajectory_format,
+            interval: 10,
+        },
+    }).await?;
     
     
     event_loop.run( move | event, _, control_flow|{
@@ -49,14 +118,21 @@ async fn run(event_loop: EventLoop<()>, window:Window){
             },
             Event::RedrawRequested(window_id) if window_id == window.id() => {
                 app.update();
+                if let Some(title) = app.title() {
+                    window.set_title(&title);
+                }
                 match app.render() {
                     Ok(_) => {}
-                    // Reconfigure the surface if lost
-                    Err(wgpu::SurfaceError::Lost) => app.resize(app.size),
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    // All other errors (Outdated, Timeout) should be resolved by the next frame
-                    Err(e) => eprintln!("{:?}", e),
+                    // Reconfigure the surface if lost or outdated
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => app.resize(app.size()),
+                    // Out of memory, starting over with a new device rather than quitting
+                    Err(wgpu::SurfaceError::OutOfMemory) => {
+                        if let Err(e) = app.recover() {
+                            log::error!("{:?}", e);
+                        }
+                    }
+                    // A timeout should be resolved by the next frame
+                    Err(e) => log::warn!("{:?}", e),
                 }
             },
             Event::RedrawEventsCleared | Event::MainEventsCleared => {
@@ -68,13 +144,14 @@ async fn run(event_loop: EventLoop<()>, window:Window){
     });
 }
 
-fn main() {
+fn main() -> anyhow::Result<()> {
     let event_loop = EventLoop::new();
-    let window = Window::new(&event_loop).unwrap();
+    let window = Window::new(&event_loop).context("could not create the window")?;
     #[cfg(not(target_arch = "wasm32"))]
     {
-        env_logger::init();
-        pollster::block_on(run(event_loop, window));
+        // The statistics are logged at the info level
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("boids_web=info")).init();
+        pollster::block_on(run(event_loop, window))?;
     }
     #[cfg(target_arch = "wasm32")]
     {
@@ -90,6 +167,11 @@ fn main() {
                     .ok()
             })
             .expect("couldn't append canvas to document body");
-        wasm_bindgen_futures::spawn_local(run(event_loop, window));
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(e) = run(event_loop, window).await {
+                log::error!("{:?}", e);
+            }
+        });
     }
+    Ok(())
 }
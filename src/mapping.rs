@@ -0,0 +1,120 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+// Set by wgpu waking the mapping once it completed
+struct Completion(AtomicBool);
+
+impl Wake for Completion {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Mapping of a readback buffer in progress, checked once per frame instead of waiting for it
+pub(crate) struct Mapping {
+    future: MapFuture,
+    completion: Arc<Completion>,
+    // Result of a mapping that completed as soon as it started
+    result: Option<Result<(), wgpu::BufferAsyncError>>,
+}
+
+impl Future for Mapping {
+    type Output = Result<(), wgpu::BufferAsyncError>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => self.future.as_mut().poll(context),
+        }
+    }
+}
+
+/// Starts mapping the whole buffer for reading, once the copies to it were submitted
+pub(crate) fn map_read(buffer: &wgpu::Buffer) -> Mapping {
+    let completion = Arc::new(Completion(AtomicBool::new(false)));
+    let mut future: MapFuture = Box::pin(buffer.slice(..).map_async(wgpu::MapMode::Read));
+    // Registers the waker setting the completion flag
+    let waker = Waker::from(completion.clone());
+    let result = match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(result) => Some(result),
+        Poll::Pending => None,
+    };
+    Mapping { future, completion, result }
+}
+
+/// Whether the mapping completed, the device having to be polled beforehand
+pub(crate) fn poll_mapping(mapping: &mut Mapping) -> Poll<Result<(), wgpu::BufferAsyncError>> {
+    if mapping.result.is_none() && !mapping.completion.0.load(Ordering::SeqCst) {
+        return Poll::Pending;
+    }
+    let waker = Waker::from(mapping.completion.clone());
+    Pin::new(mapping).poll(&mut Context::from_waker(&waker))
+}
+
+/// Buffer receiving copies of a GPU buffer that are read back without stalling the frames,
+/// each copy carrying a tag such as the step it was taken at
+pub(crate) struct Readback<T> {
+    buffer: wgpu::Buffer,
+    size: u64,
+    // Tag of the copy on its way back, None while the buffer is free
+    pending: Option<T>,
+    mapping: Option<Mapping>,
+}
+
+impl<T> Readback<T> {
+    pub(crate) fn new(device: &wgpu::Device, label: &str, size: u64) -> Self {
+        Self {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            size,
+            pending: None,
+            mapping: None,
+        }
+    }
+
+    /// Whether a new copy can be encoded, the previous one having been read
+    pub(crate) fn is_free(&self) -> bool {
+        self.pending.is_none()
+    }
+
+    /// Encodes a copy of the start of the source, the readback having to be free
+    pub(crate) fn copy(&mut self, encoder: &mut wgpu::CommandEncoder, source: &wgpu::Buffer, tag: T) {
+        debug_assert!(self.is_free(), "the previous copy was not read");
+        encoder.copy_buffer_to_buffer(source, 0, &self.buffer, 0, self.size);
+        self.pending = Some(tag);
+    }
+
+    /// Starts mapping the copy, once the encoder copying it was submitted
+    pub(crate) fn start_reading(&mut self) {
+        if self.pending.is_some() && self.mapping.is_none() {
+            self.mapping = Some(map_read(&self.buffer));
+        }
+    }
+
+    /// Tag and contents of the copy once they reached the CPU, waiting for the GPU when asked to
+    pub(crate) fn try_read(&mut self, device: &wgpu::Device, wait: bool) -> Option<Result<(T, Vec<u8>), wgpu::BufferAsyncError>> {
+        let mapping = self.mapping.as_mut()?;
+        device.poll(if wait { wgpu::Maintain::Wait } else { wgpu::Maintain::Poll });
+        let result = match poll_mapping(mapping) {
+            Poll::Ready(result) => result,
+            Poll::Pending if wait => pollster::block_on(mapping),
+            Poll::Pending => return None,
+        };
+        self.mapping = None;
+        let tag = self.pending.take().unwrap();
+        Some(result.map(|()| {
+            let contents = self.buffer.slice(..).get_mapped_range().to_vec();
+            self.buffer.unmap();
+            (tag, contents)
+        }))
+    }
+}
@@ -0,0 +1,233 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use anyhow::Context;
+use crate::application::Dimensions;
+use crate::boid::{decode_boids, Boid, Boid3};
+
+/// Sampling of the flock metrics by the application
+#[derive(Clone, Debug)]
+pub struct MetricsParams {
+    /// CSV file the time series is written to, None to disable the metrics
+    pub output: Option<PathBuf>,
+    /// Simulation steps between two samples
+    pub interval: u32,
+    /// Boids closer than this distance belong to the same cluster
+    pub cluster_radius: f32,
+}
+
+/// Standard collective motion observables of a flock at one step
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FlockMetrics {
+    /// Norm of the mean heading, 1 when all the boids head the same way and close to 0 when
+    /// their headings are random
+    pub polarization: f32,
+    /// Norm of the mean angular momentum of the headings around the center of the flock,
+    /// 1 when the boids all circle around it the same way
+    pub milling: f32,
+    /// Distribution of the distances from each boid to its nearest neighbour
+    pub nearest_mean: f32,
+    /// 10th percentile of the nearest neighbour distances
+    pub nearest_p10: f32,
+    /// Median of the nearest neighbour distances
+    pub nearest_p50: f32,
+    /// 90th percentile of the nearest neighbour distances
+    pub nearest_p90: f32,
+    /// Connected components of the boids linked when closer than the cluster radius
+    pub cluster_count: usize,
+    /// Boids in the largest cluster
+    pub largest_cluster: usize,
+    /// Boids per cluster on average
+    pub mean_cluster_size: f32,
+}
+
+type Vec3 = [f32; 3];
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn length(a: Vec3) -> f32 {
+    (a[0] * a[0] + a[1] * a[1] + a[2] * a[2]).sqrt()
+}
+
+fn normalized(a: Vec3) -> Vec3 {
+    let l = length(a);
+    if l > 0.0 { [a[0] / l, a[1] / l, a[2] / l] } else { [0.0; 3] }
+}
+
+fn mean(vectors: impl Iterator<Item = Vec3>) -> Vec3 {
+    let (sum, count) = vectors.fold(([0.0; 3], 0), |(s, n), v| ([s[0] + v[0], s[1] + v[1], s[2] + v[2]], n + 1));
+    match count {
+        0 => [0.0; 3],
+        n => [sum[0] / n as f32, sum[1] / n as f32, sum[2] / n as f32],
+    }
+}
+
+// Root of the set of i, flattening the path on the way
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+impl FlockMetrics {
+    /// Metrics of boids given by their positions and velocities, 2D ones having a null z.
+    /// Every pair of boids is compared, which is fine for the few thousands boids of the application
+    pub fn compute(positions: &[Vec3], velocities: &[Vec3], cluster_radius: f32) -> Self {
+        let n = positions.len();
+        if n == 0 {
+            return Self::default();
+        }
+        let headings: Vec<Vec3> = velocities.iter().map(|&v| normalized(v)).collect();
+        let polarization = length(mean(headings.iter().copied()));
+        let center = mean(positions.iter().copied());
+        let milling = length(mean(
+            positions.iter().zip(&headings).map(|(&p, &h)| cross(normalized(sub(p, center)), h)),
+        ));
+
+        let mut nearest = vec![f32::INFINITY; n];
+        let mut parents: Vec<usize> = (0..n).collect();
+        for i in 0..n {
+            for j in i + 1..n {
+                let distance = length(sub(positions[i], positions[j]));
+                nearest[i] = nearest[i].min(distance);
+                nearest[j] = nearest[j].min(distance);
+                if distance < cluster_radius {
+                    let (a, b) = (find(&mut parents, i), find(&mut parents, j));
+                    parents[a] = b;
+                }
+            }
+        }
+
+        // A single boid has no neighbour
+        let mut nearest: Vec<f32> = nearest.into_iter().filter(|d| d.is_finite()).collect();
+        nearest.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let percentile = |p: f32| match nearest.len() {
+            0 => 0.0,
+            len => nearest[((len - 1) as f32 * p).round() as usize],
+        };
+
+        let mut cluster_sizes = vec![0; n];
+        for i in 0..n {
+            cluster_sizes[find(&mut parents, i)] += 1;
+        }
+        let cluster_sizes: Vec<usize> = cluster_sizes.into_iter().filter(|&size| size > 0).collect();
+
+        Self {
+            polarization,
+            milling,
+            nearest_mean: if nearest.is_empty() { 0.0 } else { nearest.iter().sum::<f32>() / nearest.len() as f32 },
+            nearest_p10: percentile(0.1),
+            nearest_p50: percentile(0.5),
+            nearest_p90: percentile(0.9),
+            cluster_count: cluster_sizes.len(),
+            largest_cluster: cluster_sizes.iter().copied().max().unwrap_or(0),
+            mean_cluster_size: n as f32 / cluster_sizes.len() as f32,
+        }
+    }
+
+    /// Metrics of boids as read back by BoidSimulation::read_boids
+    pub fn from_boids(dimensions: Dimensions, boids: &[u8], cluster_radius: f32) -> anyhow::Result<Self> {
+        let (positions, velocities): (Vec<Vec3>, Vec<Vec3>) = match dimensions {
+            Dimensions::Two => decode_boids::<Boid>(boids)?.iter().map(|boid| {
+                let (p, s) = (boid.position(), boid.speed());
+                ([p[0], p[1], 0.0], [s[0], s[1], 0.0])
+            }).unzip(),
+            Dimensions::Three => decode_boids::<Boid3>(boids)?.iter().map(|boid| (boid.position(), boid.speed())).unzip(),
+        };
+        Ok(Self::compute(&positions, &velocities, cluster_radius))
+    }
+}
+
+/// Time series of the flock metrics written to a CSV file, one row per sample
+pub struct MetricsLog {
+    writer: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl MetricsLog {
+    /// Creates the file and writes the header of the columns
+    pub fn create(path: PathBuf) -> anyhow::Result<Self> {
+        let mut writer = BufWriter::new(File::create(&path).with_context(|| format!("could not create {}", path.display()))?);
+        writeln!(
+            writer,
+            "step,sim_time,polarization,milling,nearest_mean,nearest_p10,nearest_p50,nearest_p90,cluster_count,largest_cluster,mean_cluster_size"
+        )?;
+        Ok(Self { writer, path })
+    }
+
+    /// Appends the metrics of a sample and flushes them to the file
+    pub fn write(&mut self, step: u32, sim_time: f32, metrics: &FlockMetrics) -> anyhow::Result<()> {
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            step, sim_time, metrics.polarization, metrics.milling,
+            metrics.nearest_mean, metrics.nearest_p10, metrics.nearest_p50, metrics.nearest_p90,
+            metrics.cluster_count, metrics.largest_cluster, metrics.mean_cluster_size,
+        )
+        .and_then(|()| self.writer.flush())
+        .with_context(|| format!("could not write to {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < EPSILON
+    }
+
+    #[test]
+    fn aligned_flock() {
+        let positions: Vec<Vec3> = (0..10).map(|i| [i as f32 * 1.5, (i * i) as f32 * 0.3, 0.0]).collect();
+        let velocities = vec![[0.6, 0.8, 0.0]; 10];
+        let metrics = FlockMetrics::compute(&positions, &velocities, 2.0);
+        assert!(close(metrics.polarization, 1.0), "{:?}", metrics);
+    }
+
+    #[test]
+    fn milling_ring() {
+        let angles: Vec<f32> = (0..12).map(|i| i as f32 * std::f32::consts::TAU / 12.0).collect();
+        let positions: Vec<Vec3> = angles.iter().map(|a| [5.0 * a.cos(), 5.0 * a.sin(), 0.0]).collect();
+        // Counterclockwise around the center
+        let velocities: Vec<Vec3> = angles.iter().map(|a| [-a.sin(), a.cos(), 0.0]).collect();
+        let metrics = FlockMetrics::compute(&positions, &velocities, 2.0);
+        assert!(close(metrics.milling, 1.0), "{:?}", metrics);
+        assert!(close(metrics.polarization, 0.0), "{:?}", metrics);
+    }
+
+    #[test]
+    fn separate_clusters() {
+        // A line of 3 boids 1 apart and a pair far from it
+        let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0], [50.0, 0.0, 0.0], [50.0, 1.0, 0.0]];
+        let velocities = [[1.0, 0.0, 0.0]; 5];
+        let metrics = FlockMetrics::compute(&positions, &velocities, 1.5);
+        assert_eq!(metrics.cluster_count, 2);
+        assert_eq!(metrics.largest_cluster, 3);
+        assert!(close(metrics.mean_cluster_size, 2.5), "{:?}", metrics);
+        assert!(close(metrics.nearest_mean, 1.0), "{:?}", metrics);
+    }
+
+    #[test]
+    fn single_boid() {
+        let metrics = FlockMetrics::compute(&[[3.0, 4.0, 0.0]], &[[1.0, 0.0, 0.0]], 2.0);
+        for value in [
+            metrics.polarization, metrics.milling, metrics.nearest_mean, metrics.nearest_p10,
+            metrics.nearest_p50, metrics.nearest_p90, metrics.mean_cluster_size,
+        ] {
+            assert!(!value.is_nan(), "{:?}", metrics);
+        }
+        assert_eq!([metrics.nearest_mean, metrics.nearest_p10, metrics.nearest_p50, metrics.nearest_p90], [0.0; 4]);
+        assert_eq!((metrics.cluster_count, metrics.largest_cluster), (1, 1));
+    }
+}
@@ -0,0 +1,55 @@
+use winit::dpi::PhysicalPosition;
+use winit::event::{WindowEvent, ElementState, MouseButton};
+
+pub struct MouseController {
+    cursor: PhysicalPosition<f64>,
+    is_attract_pressed: bool,
+    is_repel_pressed: bool,
+}
+
+impl MouseController {
+    pub(crate) fn new() -> Self {
+        Self {
+            cursor: PhysicalPosition::new(0.0, 0.0),
+            is_attract_pressed: false,
+            is_repel_pressed: false,
+        }
+    }
+
+    pub(crate) fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = *position;
+                true
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let is_pressed = *state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => {
+                        self.is_attract_pressed = is_pressed;
+                        true
+                    }
+                    MouseButton::Right => {
+                        self.is_repel_pressed = is_pressed;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn cursor(&self) -> PhysicalPosition<f64> {
+        self.cursor
+    }
+
+    // 1 when attracting, -1 when repelling, 0 when no button (or both) are held
+    pub(crate) fn force_sign(&self) -> f32 {
+        match (self.is_attract_pressed, self.is_repel_pressed) {
+            (true, false) => 1.0,
+            (false, true) => -1.0,
+            _ => 0.0,
+        }
+    }
+}
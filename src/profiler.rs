@@ -0,0 +1,120 @@
+use std::convert::TryInto;
+use std::task::Poll;
+use anyhow::Context;
+use wgpu::{Device, Queue};
+use crate::mapping::{map_read, poll_mapping, Mapping};
+
+// Size of a resolved timestamp
+const TIMESTAMP_SIZE: u64 = 8;
+
+/// GPU time of spans of command encoders, measured with a pair of timestamp queries each.
+/// Only available on devices created with `wgpu::Features::TIMESTAMP_QUERY`
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    readback_buffer: wgpu::Buffer,
+    span_count: u32,
+    // Nanoseconds per timestamp tick
+    period: f32,
+    mapping: Option<Mapping>,
+}
+
+impl GpuTimer {
+    /// None when the device has no timestamp queries
+    pub fn new(device: &Device, queue: &Queue, span_count: u32) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Timestamp query set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2 * span_count,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp readback buffer"),
+            size: 2 * span_count as u64 * TIMESTAMP_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            readback_buffer,
+            span_count,
+            period: queue.get_timestamp_period(),
+            mapping: None,
+        })
+    }
+
+    /// Number of spans that can be timed in a frame
+    pub fn span_count(&self) -> u32 {
+        self.span_count
+    }
+
+    /// Writes the timestamp starting the span
+    pub fn start(&self, encoder: &mut wgpu::CommandEncoder, span: u32) {
+        encoder.write_timestamp(&self.query_set, 2 * span);
+    }
+
+    /// Writes the timestamp ending the span
+    pub fn stop(&self, encoder: &mut wgpu::CommandEncoder, span: u32) {
+        encoder.write_timestamp(&self.query_set, 2 * span + 1);
+    }
+
+    /// Copies the timestamps to the readback buffer, after the last span was stopped
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.resolve_query_set(&self.query_set, 0..2 * self.span_count, &self.readback_buffer, 0);
+    }
+
+    /// Duration of every span in milliseconds once the resolving encoder was submitted,
+    /// waiting for the GPU
+    pub fn read(&self, device: &Device) -> anyhow::Result<Vec<f64>> {
+        let slice = self.readback_buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).context("could not read the timestamps back")?;
+        let durations = self.durations(&slice.get_mapped_range());
+        self.readback_buffer.unmap();
+        Ok(durations)
+    }
+
+    /// Starts copying the timestamps back once the resolving encoder was submitted,
+    /// try_read giving the durations when they arrive
+    pub fn request_read(&mut self) {
+        if self.mapping.is_none() {
+            self.mapping = Some(map_read(&self.readback_buffer));
+        }
+    }
+
+    /// Whether requested timestamps are still on their way, in which case the spans can't be
+    /// written or resolved again
+    pub fn is_reading(&self) -> bool {
+        self.mapping.is_some()
+    }
+
+    /// Duration of every span in milliseconds when the timestamps requested with request_read
+    /// reached the CPU, without waiting for the GPU
+    pub fn try_read(&mut self, device: &Device) -> Option<anyhow::Result<Vec<f64>>> {
+        let mapping = self.mapping.as_mut()?;
+        device.poll(wgpu::Maintain::Poll);
+        let result = match poll_mapping(mapping) {
+            Poll::Pending => return None,
+            Poll::Ready(result) => result,
+        };
+        self.mapping = None;
+        Some(result.context("could not read the timestamps back").map(|()| {
+            let durations = self.durations(&self.readback_buffer.slice(..).get_mapped_range());
+            self.readback_buffer.unmap();
+            durations
+        }))
+    }
+
+    fn durations(&self, timestamps: &[u8]) -> Vec<f64> {
+        let timestamps: Vec<u64> = timestamps
+            .chunks_exact(TIMESTAMP_SIZE as usize)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        timestamps
+            .chunks_exact(2)
+            .map(|span| span[1].wrapping_sub(span[0]) as f64 * self.period as f64 / 1e6)
+            .collect()
+    }
+}
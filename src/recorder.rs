@@ -0,0 +1,224 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use anyhow::{bail, Context};
+use wgpu::Device;
+use crate::renderer::DEPTH_FORMAT;
+
+/// Where the recorded frames go
+#[derive(Clone, Debug)]
+pub enum RecordOutput {
+    /// Numbered PNG files in a directory
+    Png(PathBuf),
+    /// Raw RGBA frames written to the standard input of an encoder, {width}, {height} and {fps}
+    /// being replaced in the command, e.g.
+    /// ffmpeg -y -f rawvideo -pix_fmt rgba -s {width}x{height} -r {fps} -i - out.mp4
+    Pipe(String),
+}
+
+/// Where and at which size the frames are recorded
+#[derive(Clone, Debug)]
+pub struct RecordingParams {
+    /// Numbered PNGs or the standard input of an encoder
+    pub output: RecordOutput,
+    /// Width of the frames in pixels, independent of the window
+    pub width: u32,
+    /// Height of the frames in pixels
+    pub height: u32,
+    /// Frames per second of the video, the simulation advancing by 1/fps per frame while recording
+    pub fps: f32,
+}
+
+enum Sink {
+    Png(PathBuf),
+    Pipe(Child),
+}
+
+/// Renders the frames offscreen at a fixed resolution and reads them back to write them out
+pub struct Recorder {
+    view: wgpu::TextureView,
+    msaa_view: Option<wgpu::TextureView>,
+    depth_view: Option<wgpu::TextureView>,
+    texture: wgpu::Texture,
+    readback_buffer: wgpu::Buffer,
+    padded_bytes_per_row: u32,
+    size: winit::dpi::PhysicalSize<u32>,
+    // The surface is usually BGRA while the outputs expect RGBA
+    swap_red_blue: bool,
+    sink: Sink,
+    frame: u32,
+}
+
+impl Recorder {
+    /// Creates the offscreen targets matching the format and sample count of the renderer,
+    /// with a depth buffer when it uses one, then the folder or the encoder process
+    pub fn start(
+        device: &Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: bool,
+        params: &RecordingParams,
+    ) -> anyhow::Result<Self> {
+        if params.width == 0 || params.height == 0 {
+            bail!("invalid recording size {}x{}", params.width, params.height);
+        }
+        let size = winit::dpi::PhysicalSize::new(params.width, params.height);
+        let sink = match &params.output {
+            RecordOutput::Png(directory) => {
+                fs::create_dir_all(directory).with_context(|| format!("could not create {}", directory.display()))?;
+                Sink::Png(directory.clone())
+            }
+            RecordOutput::Pipe(command) => {
+                let command = command
+                    .replace("{width}", &params.width.to_string())
+                    .replace("{height}", &params.height.to_string())
+                    .replace("{fps}", &params.fps.to_string());
+                let mut words = command.split_whitespace();
+                let program = words.next().context("empty encoder command")?;
+                let child = Command::new(program)
+                    .args(words)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .with_context(|| format!("could not start {}", program))?;
+                Sink::Pipe(child)
+            }
+        };
+
+        let extent = wgpu::Extent3d { width: params.width, height: params.height, depth_or_array_layers: 1 };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Recording Texture"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let attachment = |label, format, sample_count| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: extent,
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            }).create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let msaa_view = (sample_count > 1).then(|| attachment("Recording Multisampled Texture", format, sample_count));
+        let depth_view = depth.then(|| attachment("Recording Depth Texture", DEPTH_FORMAT, sample_count));
+
+        // Rows of a texture copy have to be aligned
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (params.width * 4).div_ceil(align) * align;
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Recording readback buffer"),
+            size: (padded_bytes_per_row * params.height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Ok(Self {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            msaa_view,
+            depth_view,
+            texture,
+            readback_buffer,
+            padded_bytes_per_row,
+            size,
+            swap_red_blue: matches!(format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb),
+            sink,
+            frame: 0,
+        })
+    }
+
+    /// Size of the recorded frames
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
+
+    /// The texture the frames are rendered into
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// The multisampled texture resolved into view, when multisampling
+    pub fn msaa_view(&self) -> Option<&wgpu::TextureView> {
+        self.msaa_view.as_ref()
+    }
+
+    /// The depth buffer, when the renderer uses one
+    pub fn depth_view(&self) -> Option<&wgpu::TextureView> {
+        self.depth_view.as_ref()
+    }
+
+    /// Copies the rendered frame to the readback buffer, to be encoded after the render passes
+    pub fn copy_frame(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(self.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d { width: self.size.width, height: self.size.height, depth_or_array_layers: 1 },
+        );
+    }
+
+    /// Waits for the copied frame and writes it out, to be called once the copy is submitted
+    pub fn save_frame(&mut self, device: &Device) -> anyhow::Result<()> {
+        let slice = self.readback_buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).context("could not read the frame back")?;
+
+        let row_size = (self.size.width * 4) as usize;
+        let mut pixels = Vec::with_capacity(row_size * self.size.height as usize);
+        for row in slice.get_mapped_range().chunks(self.padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..row_size]);
+        }
+        self.readback_buffer.unmap();
+        if self.swap_red_blue {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        // Nothing blends with the frame once written out
+        for pixel in pixels.chunks_mut(4) {
+            pixel[3] = 255;
+        }
+
+        match &mut self.sink {
+            Sink::Png(directory) => {
+                let path = directory.join(format!("frame_{:05}.png", self.frame));
+                let file = File::create(&path).with_context(|| format!("could not create {}", path.display()))?;
+                let mut encoder = png::Encoder::new(BufWriter::new(file), self.size.width, self.size.height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.write_header()?.write_image_data(&pixels)?;
+            }
+            Sink::Pipe(child) => {
+                let stdin = child.stdin.as_mut().context("encoder input closed")?;
+                stdin.write_all(&pixels).context("could not write to the encoder")?;
+            }
+        }
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// Closes the output, waiting for the encoder to finish the video
+    pub fn stop(self) -> anyhow::Result<u32> {
+        if let Sink::Pipe(mut child) = self.sink {
+            drop(child.stdin.take());
+            let status = child.wait().context("could not wait for the encoder")?;
+            if !status.success() {
+                bail!("encoder exited with {}", status);
+            }
+        }
+        Ok(self.frame)
+    }
+}
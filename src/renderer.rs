@@ -0,0 +1,661 @@
+use wgpu::{Device, Queue, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, PipelineLayoutDescriptor, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferSize};
+use wgpu::util::DeviceExt;
+use anyhow::bail;
+use bytemuck::{Zeroable, Pod};
+use crate::application::{Dimensions, SimulationParams, RenderParams, HeatmapMode, DebugView};
+use crate::background::Background;
+use crate::boid::{Boid, Boid3};
+use crate::camera::{Camera, CameraUniform, OrbitCamera, OrbitCameraUniform};
+use crate::debug::DebugOverlay;
+use crate::heatmap::Heatmap;
+use crate::shape::{ShapeVertex, build_shape_mesh};
+use crate::simulation::{BoidSimulation, BOID_STATE_SIZE};
+use crate::trail::Trails;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub(crate) struct RenderUniforms {
+    color_mode: u32,
+    colormap: u32,
+    max_speed: f32,
+    max_density: f32,
+    species_count: u32,
+    boid_size: f32,
+    shape_count: u32,
+}
+
+impl RenderParams{
+    fn create_uniforms(&self, simulation_params: &SimulationParams) -> RenderUniforms{
+        RenderUniforms{
+            color_mode: self.color_mode as u32,
+            colormap: self.colormap as u32,
+            max_speed: simulation_params.max_speed,
+            max_density: self.max_density,
+            species_count: simulation_params.species_count,
+            boid_size: self.boid_size,
+            shape_count: self.shapes.len().max(1) as u32,
+        }
+    }
+}
+
+// Dart pointing towards +y, one unit long like the 2D shapes
+const BOID_VERTICES_3D: &[[f32; 3]] = &[
+    [0.0, 0.5, 0.0],
+    [-0.225, -0.5, -0.125],
+    [0.225, -0.5, -0.125],
+    [0.0, -0.5, 0.2],
+];
+
+// Counter clockwise seen from outside
+const BOID_FACES_3D: &[[usize; 3]] = &[
+    [0, 2, 1],
+    [0, 3, 2],
+    [0, 1, 3],
+    [1, 2, 3],
+];
+
+// Unshares the vertices of the 3D mesh so that each face gets its own normal
+fn boid_mesh_3d() -> (Vec<[f32; 6]>, Vec<u16>) {
+    let sub = |a: [f32; 3], b: [f32; 3]| [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+    let mut vertices = vec![];
+    for face in BOID_FACES_3D {
+        let [a, b, c] = face.map(|i| BOID_VERTICES_3D[i]);
+        let (u, v) = (sub(b, a), sub(c, a));
+        let n = [u[1] * v[2] - u[2] * v[1], u[2] * v[0] - u[0] * v[2], u[0] * v[1] - u[1] * v[0]];
+        let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        for p in [a, b, c] {
+            vertices.push([p[0], p[1], p[2], n[0] / len, n[1] / len, n[2] / len]);
+        }
+    }
+    let indices = (0..vertices.len() as u16).collect();
+    (vertices, indices)
+}
+
+/// Format of the depth attachments given to encode_render_pass
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// Source of the draw shader holding the boid, trail, heatmap and debug passes
+pub(crate) fn draw_shader_source(dimensions: Dimensions) -> &'static str {
+    match dimensions {
+        Dimensions::Two => concat!(
+            include_str!("color.wgsl"), include_str!("draw.wgsl"),
+            include_str!("trail_draw.wgsl"), include_str!("heatmap_draw.wgsl"), include_str!("debug_draw.wgsl"),
+        ),
+        Dimensions::Three => concat!(
+            include_str!("color.wgsl"), include_str!("draw3.wgsl"),
+            include_str!("trail_draw.wgsl"), include_str!("heatmap_draw.wgsl"), include_str!("debug_draw.wgsl"),
+        ),
+    }
+}
+
+fn create_depth_view(device: &Device, size: winit::dpi::PhysicalSize<u32>, sample_count: u32) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Depth Texture"),
+        size: wgpu::Extent3d { width: size.width, height: size.height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+// Multisampled color target resolved into the surface texture, none without MSAA
+fn create_msaa_view(device: &Device, size: winit::dpi::PhysicalSize<u32>, format: wgpu::TextureFormat, sample_count: u32) -> Option<wgpu::TextureView> {
+    if sample_count <= 1 {
+        return None;
+    }
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Multisampled Frame Texture"),
+        size: wgpu::Extent3d { width: size.width, height: size.height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+    });
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}
+
+/// Draws a simulation with its overlays : background grid, heatmap, trails and debug vectors.
+/// The overlays read the buffers of the simulation the renderer was created for, which is the
+/// only one it updates and draws, a renderer being created for each new simulation
+pub struct BoidRenderer {
+    render_pipeline: RenderPipeline,
+    camera: Camera,
+    camera_uniform: CameraUniform,
+    orbit_camera: OrbitCamera,
+    orbit_camera_uniform: OrbitCameraUniform,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group: wgpu::BindGroup,
+    depth_view: Option<wgpu::TextureView>,
+    msaa_view: Option<wgpu::TextureView>,
+
+    //Buffers
+    boid_vertex_buffer: wgpu::Buffer,
+    boid_triangle_buffer: wgpu::Buffer,
+    camera_buffer: wgpu::Buffer,
+    render_buffer: wgpu::Buffer,
+
+    trails: Trails,
+    heatmap: Heatmap,
+    background: Option<Background>,
+    debug_overlay: DebugOverlay,
+
+    render_params: RenderParams,
+    dimensions: Dimensions,
+    // The simulation the overlays were created for
+    simulation_id: u64,
+    format: wgpu::TextureFormat,
+    size: winit::dpi::PhysicalSize<u32>,
+    index_count: u32,
+}
+
+impl BoidRenderer {
+    /// A renderer drawing into targets of the given format and size, msaa_samples being
+    /// brought back to 1 or 4
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        format: wgpu::TextureFormat,
+        size: winit::dpi::PhysicalSize<u32>,
+        simulation: &BoidSimulation,
+        mut render_params: RenderParams,
+    ) -> Self {
+        let simulation_params = simulation.params();
+        let dimensions = simulation_params.dimensions;
+
+        // Only 1 and 4 samples are guaranteed to be supported
+        render_params.msaa_samples = if render_params.msaa_samples > 1 { 4 } else { 1 };
+        let sample_count = render_params.msaa_samples;
+
+        let shader = device.create_shader_module(&ShaderModuleDescriptor{
+            label: Some("RenderBoids"),
+            source: wgpu::ShaderSource::Wgsl(draw_shader_source(dimensions).into())
+        });
+
+        let camera_uniform_size = match dimensions {
+            Dimensions::Two => std::mem::size_of::<CameraUniform>(),
+            Dimensions::Three => std::mem::size_of::<OrbitCameraUniform>(),
+        };
+
+        let camera_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("CameraBindGroup"),
+            entries: &[
+                BindGroupLayoutEntry{
+                    binding: 0,
+                    // The background unprojects the fragments
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(camera_uniform_size as u64)
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(std::mem::size_of::<RenderUniforms>() as u64)
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None
+                }
+            ]
+        });
+
+        let camera = Camera::new();
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera, size);
+        let orbit_camera = OrbitCamera::new();
+        let mut orbit_camera_uniform = OrbitCameraUniform::new();
+        orbit_camera_uniform.update_view_proj(&orbit_camera, size);
+
+        let camera_contents = match dimensions {
+            Dimensions::Two => bytemuck::cast_slice(&[camera_uniform]).to_vec(),
+            Dimensions::Three => bytemuck::cast_slice(&[orbit_camera_uniform]).to_vec(),
+        };
+        let camera_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Camera Buffer"),
+                contents: &camera_contents,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+
+        let render_uniform = render_params.create_uniforms(simulation_params);
+        let render_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Render Buffer"),
+                contents: bytemuck::cast_slice(&[render_uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        // The 2D shapes, the sprites among them reading from a single atlas
+        let (shape_vertices, shape_indices, atlas) = build_shape_mesh(&render_params.shapes);
+        let sprite_texture = device.create_texture_with_data(queue, &wgpu::TextureDescriptor {
+            label: Some("Sprite Atlas"),
+            size: wgpu::Extent3d { width: atlas.width, height: atlas.height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        }, &atlas.pixels);
+        let sprite_view = sprite_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sprite_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Sprite Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: render_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&sprite_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&sprite_sampler),
+                }
+            ],
+            label: Some("camera_bind_group"),
+        });
+
+        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor{
+            label: Some("RenderPipelineLayout"),
+            bind_group_layouts: &[&camera_bind_group_layout],
+            push_constant_ranges: &[]
+        });
+        
+        let instance_attributes_2d = wgpu::vertex_attr_array![ 0=>Float32x2, 1=>Float32x2, 2=>Float32x4, 5=>Uint32];
+        let vertex_attributes_2d = wgpu::vertex_attr_array![ 3=>Float32x2, 4=>Float32x2, 7=>Uint32 ];
+        // The vec3 of the 3D boids are 16 bytes aligned
+        let instance_attributes_3d = [
+            wgpu::VertexAttribute{ format: wgpu::VertexFormat::Float32x3, offset: 0, shader_location: 0 },
+            wgpu::VertexAttribute{ format: wgpu::VertexFormat::Float32x3, offset: 16, shader_location: 1 },
+            wgpu::VertexAttribute{ format: wgpu::VertexFormat::Float32x4, offset: 32, shader_location: 2 },
+            wgpu::VertexAttribute{ format: wgpu::VertexFormat::Uint32, offset: 48, shader_location: 5 },
+        ];
+        let state_attributes = [
+            wgpu::VertexAttribute{ format: wgpu::VertexFormat::Uint32, offset: 4, shader_location: 6 },
+        ];
+        let vertex_attributes_3d = wgpu::vertex_attr_array![ 3=>Float32x3, 4=>Float32x3 ];
+        let vertex_buffers = match dimensions {
+            Dimensions::Two => [
+                wgpu::VertexBufferLayout{
+                    array_stride: std::mem::size_of::<Boid>() as u64,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &instance_attributes_2d
+                },
+                wgpu::VertexBufferLayout{
+                    array_stride: std::mem::size_of::<ShapeVertex>() as u64,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &vertex_attributes_2d
+                },
+                wgpu::VertexBufferLayout{
+                    array_stride: BOID_STATE_SIZE,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &state_attributes
+                }
+            ],
+            Dimensions::Three => [
+                wgpu::VertexBufferLayout{
+                    array_stride: std::mem::size_of::<Boid3>() as u64,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &instance_attributes_3d
+                },
+                wgpu::VertexBufferLayout{
+                    array_stride: 6 * 4,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &vertex_attributes_3d
+                },
+                wgpu::VertexBufferLayout{
+                    array_stride: BOID_STATE_SIZE,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &state_attributes
+                }
+            ],
+        };
+
+        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor{
+            label: Some("RenderPipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module:&shader,
+                entry_point: "vs_main",
+                buffers: &vertex_buffers
+            },
+            primitive:  wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: match dimensions {
+                    Dimensions::Two => Some(wgpu::Face::Back),
+                    Dimensions::Three => None,
+                },
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: match dimensions {
+                Dimensions::Two => None,
+                Dimensions::Three => Some(wgpu::DepthStencilState {
+                    format: DEPTH_FORMAT,
+                    // Translucent boids must not hide the ones drawn after them
+                    depth_write_enabled: !render_params.alpha_blending,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+            },
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(wgpu::FragmentState{
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState{
+                    format,
+                    blend: Some(if render_params.alpha_blending {
+                        wgpu::BlendState::ALPHA_BLENDING
+                    } else {
+                        wgpu::BlendState::REPLACE
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL
+                }]
+            }),
+            multiview: None
+        });
+
+
+        let (mesh_vertices, mesh_indices) = match dimensions {
+            Dimensions::Two => (bytemuck::cast_slice(&shape_vertices).to_vec(), shape_indices),
+            Dimensions::Three => {
+                let (vertices, indices) = boid_mesh_3d();
+                (bytemuck::cast_slice(&vertices).to_vec(), indices)
+            }
+        };
+        let index_count = mesh_indices.len() as u32;
+
+        let boid_vertex_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Vertex Buffer"),
+                contents: &mesh_vertices,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let boid_triangle_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Index Buffer"),
+                contents: bytemuck::cast_slice(&mesh_indices),
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let depth_view = match dimensions {
+            Dimensions::Two => None,
+            Dimensions::Three => Some(create_depth_view(device, size, sample_count)),
+        };
+        let msaa_view = create_msaa_view(device, size, format, sample_count);
+
+        let boid_buffers = simulation.boid_buffers();
+        let boid_count = simulation.boid_count();
+        let trails = Trails::new(device, &camera_bind_group_layout, format, dimensions, boid_buffers, boid_count, &render_params);
+        let mut debug_overlay = DebugOverlay::new(device, &camera_bind_group_layout, format, simulation, &render_params);
+        debug_overlay.show_all(queue, render_params.debug == DebugView::All);
+        let background = match dimensions {
+            Dimensions::Two => Some(Background::new(device, &camera_bind_group_layout, format, &camera, size, &render_params)),
+            Dimensions::Three => None,
+        };
+        let heatmap = Heatmap::new(device, &camera_bind_group_layout, format, dimensions, boid_buffers, boid_count, &render_params);
+
+        Self {
+            render_pipeline,
+            camera,
+            camera_uniform,
+            orbit_camera,
+            orbit_camera_uniform,
+            camera_bind_group_layout,
+            camera_bind_group,
+            depth_view,
+            msaa_view,
+            boid_vertex_buffer,
+            boid_triangle_buffer,
+            camera_buffer,
+            render_buffer,
+            trails,
+            heatmap,
+            background,
+            debug_overlay,
+            render_params,
+            dimensions,
+            simulation_id: simulation.id(),
+            format,
+            size,
+            index_count,
+        }
+    }
+
+    /// The parameters the renderer was created with, and changed since
+    pub fn params(&self) -> &RenderParams {
+        &self.render_params
+    }
+
+    /// The colors, the trails and the debug view have to be applied with apply_params once changed,
+    /// the other parameters are only read when creating the renderer
+    pub fn params_mut(&mut self) -> &mut RenderParams {
+        &mut self.render_params
+    }
+
+    /// Uploads the parameters changed through params_mut, along with the maximum speed and the
+    /// species count of the simulation the colors depend on
+    pub fn apply_params(&mut self, queue: &Queue, simulation_params: &SimulationParams) {
+        let render_uniform = self.render_params.create_uniforms(simulation_params);
+        queue.write_buffer(&self.render_buffer, 0, bytemuck::cast_slice(&[render_uniform]));
+        self.debug_overlay.show_all(queue, self.render_params.debug == DebugView::All);
+    }
+
+    /// Starts the trails over
+    pub fn clear_trails(&mut self) {
+        self.trails.clear();
+    }
+
+    /// Layout of the group 0 every pipeline of the renderer is bound to
+    pub fn camera_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.camera_bind_group_layout
+    }
+
+    /// Size of the targets render draws into
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
+
+    /// Format of the targets render draws into
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    /// Samples per pixel of the render passes, 1 without multisampling
+    pub fn sample_count(&self) -> u32 {
+        self.render_params.msaa_samples
+    }
+
+    /// Whether the render passes need a depth attachment
+    pub fn uses_depth(&self) -> bool {
+        self.depth_view.is_some()
+    }
+
+    /// Camera of the 2D mode
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    /// Camera of the 2D mode, written to the GPU by write_camera
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// Camera of the 3D mode
+    pub fn orbit_camera(&self) -> &OrbitCamera {
+        &self.orbit_camera
+    }
+
+    /// Camera of the 3D mode, written to the GPU by write_camera
+    pub fn orbit_camera_mut(&mut self) -> &mut OrbitCamera {
+        &mut self.orbit_camera
+    }
+
+    /// Length in world units of the scale bar drawn by the background, None when it isn't shown
+    pub fn scale_bar_length(&self) -> Option<f32> {
+        self.background.as_ref().filter(|_| self.render_params.background).map(Background::scale_bar_length)
+    }
+
+    /// Has to be called whenever the size of the window changes
+    pub fn resize(&mut self, device: &Device, queue: &Queue, size: winit::dpi::PhysicalSize<u32>) {
+        self.size = size;
+        let sample_count = self.render_params.msaa_samples;
+        if self.depth_view.is_some() {
+            self.depth_view = Some(create_depth_view(device, size, sample_count));
+        }
+        self.msaa_view = create_msaa_view(device, size, self.format, sample_count);
+        self.write_camera(queue, size);
+    }
+
+    /// The projection depends on the size of the target being rendered to, has to be called
+    /// whenever a camera moves
+    pub fn write_camera(&mut self, queue: &Queue, size: winit::dpi::PhysicalSize<u32>) {
+        match self.dimensions {
+            Dimensions::Two => {
+                self.camera_uniform.update_view_proj(&self.camera, size);
+                queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+                if let Some(background) = &mut self.background {
+                    background.update(queue, &self.camera, size);
+                }
+            }
+            Dimensions::Three => {
+                self.orbit_camera_uniform.update_view_proj(&self.orbit_camera, size);
+                queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.orbit_camera_uniform]))
+            }
+        }
+    }
+
+    /// Updates the trails and the heatmap from the latest step of the simulation
+    pub fn update(&mut self, queue: &Queue, encoder: &mut wgpu::CommandEncoder, simulation: &BoidSimulation) -> anyhow::Result<()> {
+        self.check_simulation(simulation)?;
+        if self.render_params.trails {
+            self.trails.record(queue, encoder, simulation.current_buffer());
+        }
+        if self.render_params.heatmap != HeatmapMode::Off {
+            self.heatmap.update(encoder, simulation.current_buffer());
+        }
+        Ok(())
+    }
+
+    /// Draws into a target of the size given to the renderer
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, simulation: &BoidSimulation) -> anyhow::Result<()> {
+        self.encode_render_pass(encoder, simulation, view, self.msaa_view.as_ref(), self.depth_view.as_ref())
+    }
+
+    // The overlays would show the boids of the simulation the renderer was created for
+    fn check_simulation(&self, simulation: &BoidSimulation) -> anyhow::Result<()> {
+        if simulation.id() != self.simulation_id {
+            bail!("the renderer was created for another simulation");
+        }
+        Ok(())
+    }
+
+    /// Draws everything into view, through msaa_view when multisampling
+    pub fn encode_render_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        simulation: &BoidSimulation,
+        view: &wgpu::TextureView,
+        msaa_view: Option<&wgpu::TextureView>,
+        depth_view: Option<&wgpu::TextureView>,
+    ) -> anyhow::Result<()> {
+        self.check_simulation(simulation)?;
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            // With MSAA the boids are drawn into the multisampled texture then resolved into the frame
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: msaa_view.unwrap_or(view),
+                resolve_target: msaa_view.map(|_| view),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.0,
+                        g: 0.0,
+                        b: 0.0,
+                        a: 1.0,
+                    }),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: depth_view.map(|view| wgpu::RenderPassDepthStencilAttachment {
+                view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        if let (true, Some(background)) = (self.render_params.background, &self.background) {
+            background.draw(&mut render_pass);
+        }
+        if self.render_params.heatmap != HeatmapMode::Off {
+            self.heatmap.draw(&mut render_pass);
+        }
+        if self.render_params.trails {
+            self.trails.draw(&mut render_pass, &simulation.boid_buffers()[simulation.current_buffer()]);
+        }
+        if self.render_params.heatmap != HeatmapMode::Instead {
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_vertex_buffer(0, simulation.boid_buffers()[simulation.current_buffer()].slice(..));
+            render_pass.set_vertex_buffer(1, self.boid_vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(2, simulation.state_buffer().slice(..));
+            render_pass.set_index_buffer(self.boid_triangle_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            render_pass.draw_indexed(0..self.index_count,0,0..simulation.boid_count());
+        }
+        if self.render_params.debug != DebugView::Off {
+            self.debug_overlay.draw(&mut render_pass, simulation.current_buffer());
+        }
+        Ok(())
+    }
+}
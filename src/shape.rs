@@ -0,0 +1,191 @@
+use std::f32::consts::PI;
+use std::fs::File;
+use std::path::Path;
+use anyhow::{bail, Context};
+use bytemuck::{Pod, Zeroable};
+
+const CIRCLE_SEGMENTS: usize = 24;
+
+/// RGBA pixels of a sprite, rows going from the top of the image down
+#[derive(Clone)]
+pub struct SpriteImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl std::fmt::Debug for SpriteImage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SpriteImage({}x{})", self.width, self.height)
+    }
+}
+
+/// Glyph drawn for the boids of a species, one unit long and pointing towards +y before being
+/// scaled by the boid size and rotated along the velocity
+#[derive(Clone, Debug)]
+pub enum BoidShape {
+    /// Arrow head with a notch at the back
+    Arrow,
+    /// Isosceles triangle
+    Triangle,
+    /// Disc made of a fan of triangles
+    Circle,
+    /// Outline of a polygon, triangulated as a fan from its first point
+    Polygon(Vec<[f32; 2]>),
+    /// Textured quad one unit high, tinted by the boid color
+    Sprite(SpriteImage),
+}
+
+impl BoidShape {
+    /// A sprite loaded from a PNG file
+    pub fn sprite(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("could not open sprite {}", path.display()))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().with_context(|| format!("invalid PNG {}", path.display()))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+
+        let mut pixels = Vec::with_capacity((info.width * info.height * 4) as usize);
+        for line in buffer.chunks(info.line_size).take(info.height as usize) {
+            for x in 0..info.width as usize {
+                match info.color_type {
+                    png::ColorType::Rgba => pixels.extend_from_slice(&line[x * 4..x * 4 + 4]),
+                    png::ColorType::Rgb => pixels.extend_from_slice(&[line[x * 3], line[x * 3 + 1], line[x * 3 + 2], 255]),
+                    png::ColorType::GrayscaleAlpha => pixels.extend_from_slice(&[line[x * 2], line[x * 2], line[x * 2], line[x * 2 + 1]]),
+                    png::ColorType::Grayscale => pixels.extend_from_slice(&[line[x], line[x], line[x], 255]),
+                    color_type => bail!("unsupported color type {:?} for sprite {}", color_type, path.display()),
+                }
+            }
+        }
+        Ok(BoidShape::Sprite(SpriteImage { width: info.width, height: info.height, pixels }))
+    }
+
+    /// Parses a shape given on the command line :
+    /// `arrow`, `triangle`, `circle`, `sprite:<png path>` or `polygon:x,y;x,y;x,y`
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        match spec.split_once(':') {
+            None => match spec {
+                "arrow" => Ok(BoidShape::Arrow),
+                "triangle" => Ok(BoidShape::Triangle),
+                "circle" => Ok(BoidShape::Circle),
+                _ => bail!("unknown boid shape {}", spec),
+            },
+            Some(("sprite", path)) => Self::sprite(path),
+            Some(("polygon", points)) => {
+                let points = points.split(';').map(|point| {
+                    let (x, y) = point.split_once(',').with_context(|| format!("invalid polygon point {}", point))?;
+                    Ok([x.trim().parse()?, y.trim().parse()?])
+                }).collect::<anyhow::Result<Vec<[f32; 2]>>>()?;
+                if points.len() < 3 {
+                    bail!("a polygon needs at least 3 points, got {}", points.len());
+                }
+                Ok(BoidShape::Polygon(points))
+            }
+            Some((kind, _)) => bail!("unknown boid shape {}", kind),
+        }
+    }
+
+    fn outline(&self) -> Vec<[f32; 2]> {
+        match self {
+            BoidShape::Arrow => vec![[0.0, 0.5], [-0.225, -0.5], [0.0, -0.325], [0.225, -0.5]],
+            BoidShape::Triangle => vec![[0.0, 0.5], [-0.35, -0.5], [0.35, -0.5]],
+            BoidShape::Circle => (0..CIRCLE_SEGMENTS).map(|i| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+                [0.5 * angle.sin(), 0.5 * angle.cos()]
+            }).collect(),
+            BoidShape::Polygon(points) => points.clone(),
+            BoidShape::Sprite(image) => {
+                let half_width = 0.5 * image.width as f32 / image.height as f32;
+                vec![[-half_width, -0.5], [half_width, -0.5], [half_width, 0.5], [-half_width, 0.5]]
+            }
+        }
+    }
+}
+
+/// Untextured vertices have a negative uv
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct ShapeVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+    shape: u32,
+}
+
+/// All the sprites stacked from top to bottom, a transparent row separating them
+pub struct SpriteAtlas {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) pixels: Vec<u8>,
+}
+
+fn build_atlas(shapes: &[BoidShape]) -> (SpriteAtlas, Vec<Option<[f32; 4]>>) {
+    let sprites = || shapes.iter().filter_map(|shape| match shape {
+        BoidShape::Sprite(image) => Some(image),
+        _ => None,
+    });
+    let width = sprites().map(|image| image.width).max().unwrap_or(1);
+    let height = sprites().map(|image| image.height + 1).sum::<u32>().max(1);
+    if sprites().next().is_none() {
+        // A single white pixel so that the texture can always be bound
+        return (SpriteAtlas { width: 1, height: 1, pixels: vec![255; 4] }, vec![None; shapes.len()]);
+    }
+
+    let mut pixels = vec![0; (width * height * 4) as usize];
+    let mut row = 0;
+    let regions = shapes.iter().map(|shape| match shape {
+        BoidShape::Sprite(image) => {
+            for y in 0..image.height {
+                let src = (y * image.width * 4) as usize;
+                let dst = ((row + y) * width * 4) as usize;
+                pixels[dst..dst + (image.width * 4) as usize].copy_from_slice(&image.pixels[src..src + (image.width * 4) as usize]);
+            }
+            let region = [
+                0.0,
+                row as f32 / height as f32,
+                image.width as f32 / width as f32,
+                (row + image.height) as f32 / height as f32,
+            ];
+            row += image.height + 1;
+            Some(region)
+        }
+        _ => None,
+    }).collect();
+    (SpriteAtlas { width, height, pixels }, regions)
+}
+
+/// Merges the shapes in a single mesh, each vertex knowing which shape it belongs to so that
+/// the vertex shader can drop the ones not matching the species of the boid being drawn
+pub fn build_shape_mesh(shapes: &[BoidShape]) -> (Vec<ShapeVertex>, Vec<u16>, SpriteAtlas) {
+    let shapes = if shapes.is_empty() { std::slice::from_ref(&BoidShape::Arrow) } else { shapes };
+    let (atlas, regions) = build_atlas(shapes);
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    for (index, (shape, region)) in shapes.iter().zip(regions).enumerate() {
+        let mut outline = shape.outline();
+        // Back faces are culled so every outline is made counter clockwise
+        let area: f32 = (0..outline.len()).map(|i| {
+            let (a, b) = (outline[i], outline[(i + 1) % outline.len()]);
+            a[0] * b[1] - b[0] * a[1]
+        }).sum();
+        if area < 0.0 {
+            outline.reverse();
+        }
+
+        let start = vertices.len() as u16;
+        vertices.extend(outline.iter().enumerate().map(|(i, &position)| ShapeVertex {
+            position,
+            uv: match region {
+                // The outline of a sprite goes from its bottom left corner counter clockwise
+                Some([u0, v0, u1, v1]) => [[u0, v1], [u1, v1], [u1, v0], [u0, v0]][i],
+                None => [-1.0, -1.0],
+            },
+            shape: index as u32,
+        }));
+        for i in 1..outline.len() as u16 - 1 {
+            indices.extend_from_slice(&[start, start + i, start + i + 1]);
+        }
+    }
+    (vertices, indices, atlas)
+}
@@ -0,0 +1,564 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use wgpu::{Device, Queue, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferSize, BindGroupDescriptor, ComputePipeline, ComputePassDescriptor, PipelineLayoutDescriptor, ShaderModuleDescriptor};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use anyhow::{bail, Context};
+use bytemuck::{Pod, Zeroable};
+use crate::application::{Dimensions, Neighbourhood, SimulationParams, MAX_NEIGHBOURS};
+use crate::boid::{Boid, Boid3};
+use crate::debug::DEBUG_VECTOR_SIZE;
+use crate::flow_field::FlowField;
+use crate::waypoints::{WaypointPath, build_path_buffers, path_count};
+
+// Identifies the simulations, telling a renderer whether it is given the one it was created for
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub(crate) struct SimuUniforms {
+    delta_time: f32,
+    separation_reach: f32,
+    separation_scale: f32,
+    alignement_reach: f32,
+    alignement_scale: f32,
+    cohesion_reach: f32,
+    cohesion_scale: f32,
+    color_mult: f32,
+    center_attraction: f32,
+    topological: u32,
+    separation_k: u32,
+    alignement_k: u32,
+    cohesion_k: u32,
+    min_speed: f32,
+    max_speed: f32,
+    inertia: f32,
+    max_steering_force: f32,
+    flow_origin_x: f32,
+    flow_origin_y: f32,
+    flow_cell_size: f32,
+    flow_width: u32,
+    flow_height: u32,
+    flow_scale: f32,
+    path_count: u32,
+    goal_scale: f32,
+    mouse_x: f32,
+    mouse_y: f32,
+    mouse_force: f32,
+    mouse_radius: f32,
+    debug_vectors: u32,
+}
+
+impl SimulationParams{
+    fn create_uniforms(&self, delta_time: f32)-> SimuUniforms{
+        SimuUniforms{
+            delta_time,
+            separation_reach: self.separation_reach,
+            separation_scale: self.separation_scale,
+            alignement_reach: self.alignement_reach,
+            alignement_scale: self.alignement_scale,
+            cohesion_reach: self.cohesion_reach,
+            cohesion_scale: self.cohesion_scale,
+            color_mult: self.color_mult,
+            center_attraction: self.center_attraction,
+            topological: (self.neighbourhood == Neighbourhood::Topological) as u32,
+            separation_k: self.separation_k.min(MAX_NEIGHBOURS),
+            alignement_k: self.alignement_k.min(MAX_NEIGHBOURS),
+            cohesion_k: self.cohesion_k.min(MAX_NEIGHBOURS),
+            min_speed: self.min_speed.max(0.0),
+            max_speed: self.max_speed.max(self.min_speed),
+            // A null inertia would make the acceleration infinite
+            inertia: self.inertia.max(f32::EPSILON),
+            max_steering_force: self.max_steering_force.max(0.0),
+            flow_origin_x: self.flow_field.origin[0],
+            flow_origin_y: self.flow_field.origin[1],
+            flow_cell_size: self.flow_field.cell_size,
+            flow_width: self.flow_field.width,
+            flow_height: self.flow_field.height,
+            flow_scale: self.flow_scale,
+            path_count: if self.follow_paths { path_count(&self.paths) } else { 0 },
+            goal_scale: self.goal_scale,
+            mouse_x: 0.0,
+            mouse_y: 0.0,
+            mouse_force: 0.0,
+            mouse_radius: self.mouse_radius,
+            debug_vectors: 0,
+        }
+    }
+}
+
+impl SimuUniforms{
+    fn update(&mut self, delta_time: f32){
+        self.delta_time = delta_time;
+    }
+
+    fn update_mouse(&mut self, position: [f32; 2], force: f32){
+        self.mouse_x = position[0];
+        self.mouse_y = position[1];
+        self.mouse_force = force;
+    }
+
+    fn update_debug(&mut self, debug_vectors: bool){
+        self.debug_vectors = debug_vectors as u32;
+    }
+}
+
+// Per boid state written by the compute pass, also read as an instance buffer when rendering
+pub(crate) const BOID_STATE_SIZE: u64 = 8;
+
+fn create_path_buffers(device: &Device, paths: &[WaypointPath]) -> (wgpu::Buffer, wgpu::Buffer) {
+    let (headers, waypoints) = build_path_buffers(paths);
+    let path_buffer = device.create_buffer_init(&BufferInitDescriptor{
+        label: Some("Path buffer"),
+        contents: bytemuck::cast_slice(&headers),
+        usage: wgpu::BufferUsages::STORAGE
+    });
+    let waypoint_buffer = device.create_buffer_init(&BufferInitDescriptor{
+        label: Some("Waypoint buffer"),
+        contents: bytemuck::cast_slice(&waypoints),
+        usage: wgpu::BufferUsages::STORAGE
+    });
+    (path_buffer, waypoint_buffer)
+}
+
+/// The flock and the compute pipeline stepping it, independent of any window or renderer.
+/// The boids are double buffered, each step reading one buffer and writing the other
+pub struct BoidSimulation {
+    compute_pipeline: ComputePipeline,
+    boid_bind_group_layout: wgpu::BindGroupLayout,
+    boid_bind_groups: Vec<wgpu::BindGroup>,
+    simu_uniform: SimuUniforms,
+    workgroup_count: u32,
+
+    //Buffers
+    boid_buffers: Vec<wgpu::Buffer>,
+    params_buffer: wgpu::Buffer,
+    flow_buffer: wgpu::Buffer,
+    path_buffer: wgpu::Buffer,
+    waypoint_buffer: wgpu::Buffer,
+    boid_state_buffer: wgpu::Buffer,
+    debug_vectors_buffer: wgpu::Buffer,
+
+    simulation_params: SimulationParams,
+    boid_count: u32,
+    boid_alpha: f32,
+    flow_capacity: usize,
+    // Kept to recreate the simulation on another device
+    compute_source: String,
+    step: u32,
+    id: u64,
+}
+
+impl BoidSimulation {
+    /// A random flock of boid_count boids, flow_capacity being the largest number of flow field
+    /// cells set_flow_field will accept
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        simulation_params: SimulationParams,
+        boid_count: u32,
+        boid_alpha: f32,
+        flow_capacity: usize,
+    ) -> Self {
+        let compute_source = match simulation_params.dimensions {
+            Dimensions::Two => include_str!("compute2.wgsl"),
+            Dimensions::Three => include_str!("compute3.wgsl"),
+        };
+        Self::with_shader(device, queue, simulation_params, boid_count, boid_alpha, flow_capacity, compute_source)
+    }
+
+    /// Same as new, stepping the flock with another compute shader. It must have the bindings
+    /// and the step entry point of compute2.wgsl or compute3.wgsl, such as compute.wgsl in 2D
+    pub fn with_shader(
+        device: &Device,
+        queue: &Queue,
+        simulation_params: SimulationParams,
+        boid_count: u32,
+        boid_alpha: f32,
+        flow_capacity: usize,
+        compute_source: &str,
+    ) -> Self {
+        let dimensions = simulation_params.dimensions;
+        let species_count = simulation_params.species_count.max(1);
+        let (initial_boid, boid_size): (Vec<u8>, usize) = match dimensions {
+            Dimensions::Two => {
+                let boids: Vec<Boid> = (0..boid_count).map(|i| Boid::rand_new().with_species(i % species_count).with_alpha(boid_alpha)).collect();
+                (bytemuck::cast_slice(&boids).to_vec(), std::mem::size_of::<Boid>())
+            }
+            Dimensions::Three => {
+                let boids: Vec<Boid3> = (0..boid_count).map(|i| Boid3::rand_new().with_species(i % species_count).with_alpha(boid_alpha)).collect();
+                (bytemuck::cast_slice(&boids).to_vec(), std::mem::size_of::<Boid3>())
+            }
+        };
+
+        let ks = [
+            ("separation_k", simulation_params.separation_k),
+            ("alignement_k", simulation_params.alignement_k),
+            ("cohesion_k", simulation_params.cohesion_k),
+        ];
+        for (name, k) in ks {
+            if k > MAX_NEIGHBOURS {
+                log::warn!("{} is {}, the topological neighbourhood is capped at {} neighbours", name, k, MAX_NEIGHBOURS);
+            }
+        }
+        let simu_uniform = simulation_params.create_uniforms(0.0);
+        let params_buffer = device.create_buffer_init(&BufferInitDescriptor{
+            label: Some("Simu params buffer"),
+            contents: bytemuck::cast_slice(&[simu_uniform]),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM
+        });
+
+        // Sized for the largest field so that any other one can be swapped in
+        let flow_field = &simulation_params.flow_field;
+        let flow_capacity = flow_capacity.max(flow_field.cells.len());
+        let flow_buffer = device.create_buffer(&wgpu::BufferDescriptor{
+            label: Some("Flow field buffer"),
+            size: (flow_capacity * std::mem::size_of::<[f32; 2]>()) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false
+        });
+        queue.write_buffer(&flow_buffer, 0, bytemuck::cast_slice(&flow_field.cells));
+
+        let (path_buffer, waypoint_buffer) = create_path_buffers(device, &simulation_params.paths);
+        let boid_state_buffer = device.create_buffer(&wgpu::BufferDescriptor{
+            label: Some("Boid state buffer"),
+            size: BOID_STATE_SIZE * boid_count as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false
+        });
+        // Only written when the debug vectors are enabled
+        let debug_vectors_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug vectors buffer"),
+            size: DEBUG_VECTOR_SIZE * boid_count as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let mut boid_buffers = vec![];
+        for _ in 0..2 {
+            boid_buffers.push(device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Boid Buffer"),
+                    contents: &initial_boid,
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
+                }
+            ));
+        }
+
+        let boid_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Boid Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry{
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(std::mem::size_of::<SimuUniforms>() as u64)
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding:1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(boid_size as u64 * boid_count as u64)
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding:2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(boid_size as u64 * boid_count as u64)
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding:3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding:4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding:5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding:6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(BOID_STATE_SIZE * boid_count as u64)
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding:7,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(DEBUG_VECTOR_SIZE * boid_count as u64)
+                    },
+                    count: None
+                }
+            ]
+        });
+
+        let compute_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor{
+            label: Some("Compute Pipeline Layout"),
+            bind_group_layouts: &[&boid_bind_group_layout],
+            push_constant_ranges: &[]
+        });
+
+        let compute_shader = device.create_shader_module(&ShaderModuleDescriptor{
+            label: Some("StepBoids"),
+            source: wgpu::ShaderSource::Wgsl(compute_source.into())
+        });
+
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor{
+            label: Some("Compute Pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &compute_shader,
+            entry_point: "step"
+        });
+
+        let workgroup_count = ((boid_count as f32) / 64_f32).ceil() as u32;
+
+        let mut simulation = Self {
+            compute_pipeline,
+            boid_bind_group_layout,
+            boid_bind_groups: vec![],
+            simu_uniform,
+            workgroup_count,
+            boid_buffers,
+            params_buffer,
+            flow_buffer,
+            path_buffer,
+            waypoint_buffer,
+            boid_state_buffer,
+            debug_vectors_buffer,
+            simulation_params,
+            boid_count,
+            boid_alpha,
+            flow_capacity,
+            compute_source: compute_source.to_string(),
+            step: 0,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        };
+        simulation.create_boid_bind_groups(device);
+        simulation
+    }
+
+    /// The same simulation on another device, such as the one replacing a lost device. The flock
+    /// starts over from the boids of the snapshot when one is given, as read by read_boids,
+    /// otherwise from random boids. The step count carries on
+    pub fn recreate(&self, device: &Device, queue: &Queue, snapshot: Option<&[u8]>) -> anyhow::Result<Self> {
+        let mut simulation = Self::with_shader(
+            device,
+            queue,
+            self.simulation_params.clone(),
+            self.boid_count,
+            self.boid_alpha,
+            self.flow_capacity,
+            &self.compute_source,
+        );
+        if let Some(snapshot) = snapshot {
+            simulation.write_boids(queue, snapshot)?;
+        }
+        simulation.step = self.step;
+        Ok(simulation)
+    }
+
+    // Has to be called whenever one of the buffers bound to the compute pass is recreated
+    fn create_boid_bind_groups(&mut self, device: &Device) {
+        self.boid_bind_groups = (0..2).map(|i| {
+            device.create_bind_group(&BindGroupDescriptor{
+                label: Some(&*format!("Boid binding group {}", i)),
+                layout: &self.boid_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry{ binding: 0, resource: self.params_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry{ binding: 1, resource: self.boid_buffers[i].as_entire_binding() },
+                    wgpu::BindGroupEntry{ binding: 2, resource: self.boid_buffers[(i+1)%2].as_entire_binding() },
+                    wgpu::BindGroupEntry{ binding: 3, resource: self.flow_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry{ binding: 4, resource: self.path_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry{ binding: 5, resource: self.waypoint_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry{ binding: 6, resource: self.boid_state_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry{ binding: 7, resource: self.debug_vectors_buffer.as_entire_binding() },
+                ]
+            })
+        }).collect();
+    }
+
+    /// The parameters the simulation was created with, and changed since
+    pub fn params(&self) -> &SimulationParams {
+        &self.simulation_params
+    }
+
+    /// Whether the boids are Boid or Boid3
+    pub fn dimensions(&self) -> Dimensions {
+        self.simulation_params.dimensions
+    }
+
+    /// Number of boids simulated
+    pub fn boid_count(&self) -> u32 {
+        self.boid_count
+    }
+
+    /// Both boid buffers, holding Boid or Boid3 depending on the dimensions
+    pub fn boid_buffers(&self) -> &[wgpu::Buffer] {
+        &self.boid_buffers
+    }
+
+    /// Index of the boid buffer written by the latest step
+    pub fn current_buffer(&self) -> usize {
+        (self.step % 2) as usize
+    }
+
+    /// Size in bytes of all the boids of a buffer
+    pub fn boids_size(&self) -> u64 {
+        let boid_size = match self.simulation_params.dimensions {
+            Dimensions::Two => std::mem::size_of::<Boid>(),
+            Dimensions::Three => std::mem::size_of::<Boid3>(),
+        };
+        boid_size as u64 * self.boid_count as u64
+    }
+
+    /// Copies the boids written by the latest step back to the CPU, waiting for the GPU
+    pub fn read_boids(&self, device: &Device, queue: &Queue) -> anyhow::Result<Vec<u8>> {
+        let size = self.boids_size();
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor{
+            label: Some("Boid readback buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor{
+            label: Some("Readback Encoder")
+        });
+        encoder.copy_buffer_to_buffer(&self.boid_buffers[self.current_buffer()], 0, &readback_buffer, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = readback_buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).context("could not read the boids back")?;
+        let boids = slice.get_mapped_range().to_vec();
+        readback_buffer.unmap();
+        Ok(boids)
+    }
+
+    /// Replaces the boids of both buffers, as laid out by Boid or Boid3
+    pub fn write_boids(&self, queue: &Queue, boids: &[u8]) -> anyhow::Result<()> {
+        if boids.len() as u64 != self.boids_size() {
+            bail!("expected {} bytes of boids, got {}", self.boids_size(), boids.len());
+        }
+        for buffer in &self.boid_buffers {
+            queue.write_buffer(buffer, 0, boids);
+        }
+        Ok(())
+    }
+
+    /// Per boid path state, usable as an instance buffer
+    pub fn state_buffer(&self) -> &wgpu::Buffer {
+        &self.boid_state_buffer
+    }
+
+    /// Separation, alignement, cohesion, center pull and velocity of every boid,
+    /// written by the steps run with the debug vectors enabled
+    pub fn debug_vectors_buffer(&self) -> &wgpu::Buffer {
+        &self.debug_vectors_buffer
+    }
+
+    /// Number of steps run so far
+    pub fn step_count(&self) -> u32 {
+        self.step
+    }
+
+    /// Unique to each simulation, including the recreated ones
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Switches the rules between the metric and the topological neighbourhood from the next step
+    pub fn set_neighbourhood(&mut self, neighbourhood: Neighbourhood) {
+        self.simulation_params.neighbourhood = neighbourhood;
+        self.simu_uniform = self.simulation_params.create_uniforms(self.simu_uniform.delta_time);
+    }
+
+    /// Position in the world of the mouse and its strength, negative to repel the boids
+    pub fn set_mouse(&mut self, position: [f32; 2], force: f32) {
+        self.simu_uniform.update_mouse(position, force);
+    }
+
+    /// Whether the steps write the steering vectors the debug overlay draws
+    pub fn set_debug_vectors(&mut self, debug_vectors: bool) {
+        self.simu_uniform.update_debug(debug_vectors);
+    }
+
+    /// Turns path following on or off, the paths starting over
+    pub fn set_follow_paths(&mut self, device: &Device, queue: &Queue, follow_paths: bool) {
+        self.simulation_params.follow_paths = follow_paths;
+        self.set_paths(device, queue, self.simulation_params.paths.clone());
+    }
+
+    /// Replaces the paths, every boid starting over from the first waypoint of its path
+    pub fn set_paths(&mut self, device: &Device, queue: &Queue, paths: Vec<WaypointPath>) {
+        let (path_buffer, waypoint_buffer) = create_path_buffers(device, &paths);
+        self.path_buffer = path_buffer;
+        self.waypoint_buffer = waypoint_buffer;
+        queue.write_buffer(&self.boid_state_buffer, 0, &vec![0; (BOID_STATE_SIZE * self.boid_count as u64) as usize]);
+        self.create_boid_bind_groups(device);
+        self.simulation_params.paths = paths;
+        self.simu_uniform = self.simulation_params.create_uniforms(self.simu_uniform.delta_time);
+    }
+
+    /// Replaces the flow field, failing when it has more cells than the flow capacity
+    pub fn set_flow_field(&mut self, queue: &Queue, flow_field: FlowField) -> anyhow::Result<()> {
+        // Growing the buffer would mean recreating the boid bind groups
+        if flow_field.cells.len() > self.flow_capacity {
+            bail!("the flow field has {} cells, more than the {} of the flow buffer", flow_field.cells.len(), self.flow_capacity);
+        }
+        queue.write_buffer(&self.flow_buffer, 0, bytemuck::cast_slice(&flow_field.cells));
+        self.simulation_params.flow_field = flow_field;
+        self.simu_uniform = self.simulation_params.create_uniforms(self.simu_uniform.delta_time);
+        Ok(())
+    }
+
+    /// Encodes a step advancing the flock by delta_time
+    pub fn step(&mut self, queue: &Queue, encoder: &mut wgpu::CommandEncoder, delta_time: f32) {
+        self.simu_uniform.update(delta_time);
+        queue.write_buffer(&self.params_buffer, 0 , bytemuck::cast_slice(&[self.simu_uniform]));
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor{ label: None });
+            compute_pass.set_pipeline(&self.compute_pipeline);
+            compute_pass.set_bind_group(0,&self.boid_bind_groups[self.current_buffer()],&[]);
+            compute_pass.dispatch(self.workgroup_count,1, 1)
+        }
+        self.step += 1;
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Frames the FPS and the percentiles are computed over
+const FRAME_WINDOW: usize = 120;
+// The window title is refreshed a few times per second rather than every frame
+const TITLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Live statistics of the simulation, shown in the window title and logged at an interval
+pub struct Stats {
+    frame_times: VecDeque<f32>,
+    // Compute and render pass times in milliseconds of the frames profiled on the GPU
+    gpu_times: VecDeque<[f32; 2]>,
+    frame_count: u64,
+    sim_time: f32,
+    boid_count: u32,
+    log_interval: Option<Duration>,
+    last_log: Instant,
+    last_title: Instant,
+}
+
+/// Statistics over the latest frames
+#[derive(Copy, Clone, Debug)]
+pub struct StatsSummary {
+    /// Frames per second
+    pub fps: f32,
+    /// Frame times in milliseconds
+    pub p50: f32,
+    /// 95th percentile of the frame times in milliseconds
+    pub p95: f32,
+    /// 99th percentile of the frame times in milliseconds
+    pub p99: f32,
+    /// Frames since the start
+    pub frame_count: u64,
+    /// Boids simulated
+    pub boid_count: u32,
+    /// Simulated time in seconds
+    pub sim_time: f32,
+    /// Mean GPU time in milliseconds of the compute and of the render passes,
+    /// when the device has timestamp queries
+    pub gpu_compute: Option<f32>,
+    /// Mean GPU time in milliseconds of the render passes
+    pub gpu_render: Option<f32>,
+}
+
+impl fmt::Display for StatsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.0} FPS | frame p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms | {} boids | sim time {:.1} s | frame {}",
+            self.fps, self.p50, self.p95, self.p99, self.boid_count, self.sim_time, self.frame_count
+        )?;
+        if let (Some(compute), Some(render)) = (self.gpu_compute, self.gpu_render) {
+            write!(f, " | GPU compute {:.2} ms, render {:.2} ms", compute, render)?;
+        }
+        Ok(())
+    }
+}
+
+impl Stats {
+    /// A null log interval disables the logging
+    pub fn new(boid_count: u32, log_interval: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            frame_times: VecDeque::with_capacity(FRAME_WINDOW),
+            gpu_times: VecDeque::with_capacity(FRAME_WINDOW),
+            frame_count: 0,
+            sim_time: 0.0,
+            boid_count,
+            log_interval: (log_interval > Duration::ZERO).then_some(log_interval),
+            last_log: now,
+            last_title: now,
+        }
+    }
+
+    /// frame_time is the wall clock time since the last frame, sim_step the simulated time
+    pub fn record(&mut self, frame_time: f32, sim_step: f32) {
+        if self.frame_times.len() == FRAME_WINDOW {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+        self.frame_count += 1;
+        self.sim_time += sim_step;
+
+        if let Some(interval) = self.log_interval {
+            if self.last_log.elapsed() >= interval {
+                self.last_log = Instant::now();
+                log::info!("{}", self.summary());
+            }
+        }
+    }
+
+    /// GPU time in milliseconds of the compute and of the render passes of a frame
+    pub fn record_gpu(&mut self, compute: f32, render: f32) {
+        if self.gpu_times.len() == FRAME_WINDOW {
+            self.gpu_times.pop_front();
+        }
+        self.gpu_times.push_back([compute, render]);
+    }
+
+    /// Simulated time so far in seconds
+    pub fn sim_time(&self) -> f32 {
+        self.sim_time
+    }
+
+    /// Statistics over the latest frames
+    pub fn summary(&self) -> StatsSummary {
+        let mut sorted: Vec<f32> = self.frame_times.iter().copied().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let percentile = |p: f32| match sorted.len() {
+            0 => 0.0,
+            len => sorted[((len - 1) as f32 * p).round() as usize] * 1000.0,
+        };
+        let total: f32 = sorted.iter().sum();
+        let gpu_mean = |pass: usize| match self.gpu_times.len() {
+            0 => None,
+            len => Some(self.gpu_times.iter().map(|times| times[pass]).sum::<f32>() / len as f32),
+        };
+        StatsSummary {
+            fps: if total > 0.0 { sorted.len() as f32 / total } else { 0.0 },
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            frame_count: self.frame_count,
+            boid_count: self.boid_count,
+            sim_time: self.sim_time,
+            gpu_compute: gpu_mean(0),
+            gpu_render: gpu_mean(1),
+        }
+    }
+
+    /// A new window title when it is time to refresh it
+    pub fn title(&mut self) -> Option<String> {
+        if self.last_title.elapsed() < TITLE_INTERVAL {
+            return None;
+        }
+        self.last_title = Instant::now();
+        Some(format!("Boids | {}", self.summary()))
+    }
+}
@@ -0,0 +1,256 @@
+use wgpu::{Device, Queue, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferSize, PipelineLayoutDescriptor, ShaderModuleDescriptor};
+use bytemuck::{Pod, Zeroable};
+use crate::application::{Dimensions, RenderParams};
+use crate::renderer::{draw_shader_source, DEPTH_FORMAT};
+use crate::boid::{Boid, Boid3};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct TrailUniforms {
+    head: u32,
+    length: u32,
+    count: u32,
+    floats_per_boid: u32,
+    dimensions: u32,
+    decay: f32,
+}
+
+// Motion trails : the last positions of each boid are recorded in a ring buffer after every step
+// and drawn as fading line strips behind the boids
+pub struct Trails {
+    record_pipeline: wgpu::ComputePipeline,
+    record_bind_groups: Vec<wgpu::BindGroup>,
+    render_pipeline: wgpu::RenderPipeline,
+    render_bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    uniform: TrailUniforms,
+    workgroup_count: u32,
+    boid_count: u32,
+}
+
+impl Trails {
+    pub fn new(
+        device: &Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        dimensions: Dimensions,
+        boid_buffers: &[wgpu::Buffer],
+        boid_count: u32,
+        render_params: &RenderParams,
+    ) -> Self {
+        let length = render_params.trail_length.max(2);
+        let (boid_size, color_offset) = match dimensions {
+            Dimensions::Two => (std::mem::size_of::<Boid>(), 16),
+            Dimensions::Three => (std::mem::size_of::<Boid3>(), 32),
+        };
+        let uniform = TrailUniforms {
+            head: 0,
+            length,
+            count: 0,
+            floats_per_boid: (boid_size / 4) as u32,
+            dimensions: match dimensions {
+                Dimensions::Two => 2,
+                Dimensions::Three => 3,
+            },
+            decay: render_params.trail_decay,
+        };
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trail params buffer"),
+            size: std::mem::size_of::<TrailUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let trail_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trail buffer"),
+            size: 16 * length as u64 * boid_count as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        // Recording
+        let record_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Trail Record Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(std::mem::size_of::<TrailUniforms>() as u64),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let record_bind_groups = boid_buffers.iter().enumerate().map(|(i, boid_buffer)| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&*format!("Trail record binding group {}", i)),
+                layout: &record_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: boid_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: trail_buffer.as_entire_binding() },
+                ],
+            })
+        }).collect();
+        let record_shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("RecordTrails"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("trail.wgsl").into()),
+        });
+        let record_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Trail Record Pipeline Layout"),
+            bind_group_layouts: &[&record_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let record_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Trail Record Pipeline"),
+            layout: Some(&record_pipeline_layout),
+            module: &record_shader,
+            entry_point: "record",
+        });
+
+        // Drawing, done by the vs_trail entry point of the boid shader to share its projection
+        let render_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Trail Render Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(std::mem::size_of::<TrailUniforms>() as u64),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Trail render binding group"),
+            layout: &render_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: trail_buffer.as_entire_binding() },
+            ],
+        });
+        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("RenderTrails"),
+            source: wgpu::ShaderSource::Wgsl(draw_shader_source(dimensions).into()),
+        });
+        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Trail Render Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &render_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Trail Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_trail",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: boid_size as u64,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &[wgpu::VertexAttribute { format: wgpu::VertexFormat::Float32x4, offset: color_offset, shader_location: 2 }],
+                }],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // Tested against the boids but not hiding anything
+            depth_stencil: match dimensions {
+                Dimensions::Two => None,
+                Dimensions::Three => Some(wgpu::DepthStencilState {
+                    format: DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+            },
+            multisample: wgpu::MultisampleState {
+                count: render_params.msaa_samples,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_trail",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            record_pipeline,
+            record_bind_groups,
+            render_pipeline,
+            render_bind_group,
+            params_buffer,
+            uniform,
+            workgroup_count: ((boid_count as f32) / 64_f32).ceil() as u32,
+            boid_count,
+        }
+    }
+
+    // Starts the trails over, the old points being ignored until overwritten
+    pub fn clear(&mut self) {
+        self.uniform.count = 0;
+    }
+
+    // Records the positions held by boid_buffers[buffer_index] as the latest trail points
+    pub fn record(&mut self, queue: &Queue, encoder: &mut wgpu::CommandEncoder, buffer_index: usize) {
+        self.uniform.head = (self.uniform.head + 1) % self.uniform.length;
+        self.uniform.count = (self.uniform.count + 1).min(self.uniform.length);
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("Trail Record Pass") });
+        compute_pass.set_pipeline(&self.record_pipeline);
+        compute_pass.set_bind_group(0, &self.record_bind_groups[buffer_index], &[]);
+        compute_pass.dispatch(self.workgroup_count, 1, 1);
+    }
+
+    // Expects the camera to be bound to group 0
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, boid_buffer: &'a wgpu::Buffer) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(1, &self.render_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, boid_buffer.slice(..));
+        render_pass.draw(0..2 * (self.uniform.length - 1), 0..self.boid_count);
+    }
+}
@@ -0,0 +1,42 @@
+// Records the boid positions into a ring buffer of trail points, read back by vs_trail in trail_draw.wgsl
+
+struct TrailParams {
+    head: u32;          // slot written by this step
+    length: u32;        // points per boid
+    count: u32;         // points recorded so far, up to length
+    floatsPerBoid: u32; // 12 for 2D boids, 16 for 3D boids
+    dimensions: u32;
+    decay: f32;
+};
+
+// The boids seen as raw floats so that both 2D and 3D boids can be recorded, the position coming first
+struct Floats {
+    values: [[stride(4)]] array<f32>;
+};
+
+struct TrailPoints {
+    points: [[stride(16)]] array<vec4<f32>>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: TrailParams;
+[[group(0), binding(1)]]
+var<storage> boids: Floats;
+[[group(0), binding(2)]]
+var<storage, read_write> trail: TrailPoints;
+
+[[stage(compute), workgroup_size(64)]]
+fn record([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>) {
+    let total = arrayLength(&boids.values) / params.floatsPerBoid;
+    let index = global_invocation_id.x;
+    if (index >= total) {
+        return;
+    }
+
+    let base = index * params.floatsPerBoid;
+    var z: f32 = 0.0;
+    if (params.dimensions == 3u) {
+        z = boids.values[base + 2u];
+    }
+    trail.points[index * params.length + params.head] = vec4<f32>(boids.values[base], boids.values[base + 1u], z, 1.0);
+}
@@ -0,0 +1,54 @@
+// Trail pass shared by draw.wgsl and draw3.wgsl, appended to them
+
+struct TrailParams {
+    head: u32;
+    length: u32;
+    count: u32;
+    floatsPerBoid: u32;
+    dimensions: u32;
+    decay: f32;
+};
+
+struct TrailPoints {
+    points: [[stride(16)]] array<vec4<f32>>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> trail_params: TrailParams;
+[[group(1), binding(1)]]
+var<storage> trail: TrailPoints;
+
+struct TrailInput {
+    [[builtin(vertex_index)]] vertex: u32;
+    [[builtin(instance_index)]] boid: u32;
+    [[location(2)]] boid_color: vec4<f32>;
+};
+
+struct TrailOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+};
+
+// Line list joining the recorded positions of a boid, age 0 being the latest one
+[[stage(vertex)]]
+fn vs_trail(in: TrailInput) -> TrailOutput {
+    let segment = in.vertex / 2u;
+    let age = segment + in.vertex % 2u;
+    var out: TrailOutput;
+    if (segment + 1u >= trail_params.count) {
+        // Segment not recorded yet, sent out of the clip space
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        out.color = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+        return out;
+    }
+    let slot = (trail_params.head + trail_params.length - age) % trail_params.length;
+    let pos = trail.points[in.boid * trail_params.length + slot];
+    out.clip_position = project(pos.xyz);
+    out.color = vec4<f32>(in.boid_color.xyz, in.boid_color.w * pow(1.0 - f32(age) / f32(trail_params.length), trail_params.decay));
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_trail(in: TrailOutput) -> [[location(0)]] vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,283 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::task::Poll;
+use anyhow::Context;
+use wgpu::Device;
+use crate::application::Dimensions;
+use crate::boid::{decode_boids, Boid, Boid3};
+use crate::mapping::{map_read, poll_mapping, Mapping};
+use crate::simulation::BoidSimulation;
+
+// Copies of the boids that can be on their way back at the same time
+const SLOT_COUNT: usize = 3;
+const BINARY_MAGIC: &[u8; 8] = b"BOIDTRJ1";
+
+/// Layout of a trajectory file
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TrajectoryFormat {
+    /// A header line then one row per boid and sample:
+    /// `step,sim_time,id,x,y[,z],vx,vy[,vz],r,g,b,a`
+    Csv,
+    /// Little endian binary starting with the magic bytes BOIDTRJ1, the dimensions (2 or 3) and
+    /// the boid count as u32. Each sample follows as its step (u32) and sim time (f32), then one
+    /// column per field holding a value per boid: id as u32, then x, y[, z], vx, vy[, vz], r, g,
+    /// b and a as f32
+    Binary,
+}
+
+/// Recording of the trajectories by the application
+#[derive(Clone, Debug)]
+pub struct TrajectoryParams {
+    /// File the trajectories are written to, None to disable the recording
+    pub output: Option<PathBuf>,
+    /// Layout of the file
+    pub format: TrajectoryFormat,
+    /// Simulation steps between two samples
+    pub interval: u32,
+}
+
+// A staging buffer receiving a copy of the boids
+struct Slot {
+    buffer: wgpu::Buffer,
+    // Step and sim time of the boids copied, None while the slot is free
+    sample: Option<(u32, f32)>,
+    mapping: Option<Mapping>,
+}
+
+/// Writes the positions, velocities and colors of the boids to a file at an interval. The boids
+/// are copied to staging buffers mapped asynchronously, so that the rendering only waits when
+/// all of them are still on their way back
+pub struct TrajectoryRecorder {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    format: TrajectoryFormat,
+    dimensions: Dimensions,
+    slots: Vec<Slot>,
+    // Slots holding a copy, oldest first
+    pending: VecDeque<usize>,
+    samples: u32,
+}
+
+impl TrajectoryRecorder {
+    /// Creates the file, writes its header and the staging buffers the boids are copied to
+    pub fn start(device: &Device, simulation: &BoidSimulation, path: PathBuf, format: TrajectoryFormat) -> anyhow::Result<Self> {
+        let mut writer = BufWriter::new(File::create(&path).with_context(|| format!("could not create {}", path.display()))?);
+        let dimensions = simulation.dimensions();
+        write_header(&mut writer, format, dimensions, simulation.boid_count())?;
+
+        let slots = (0..SLOT_COUNT).map(|_| Slot {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Trajectory readback buffer"),
+                size: simulation.boids_size(),
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            sample: None,
+            mapping: None,
+        }).collect();
+        Ok(Self {
+            writer,
+            path,
+            format,
+            dimensions,
+            slots,
+            pending: VecDeque::with_capacity(SLOT_COUNT),
+            samples: 0,
+        })
+    }
+
+    /// Encodes a copy of the boids written by the latest step, waiting for the oldest copy
+    /// when no staging buffer is free
+    pub fn copy_boids(&mut self, device: &Device, encoder: &mut wgpu::CommandEncoder, simulation: &BoidSimulation, sim_time: f32) -> anyhow::Result<()> {
+        if self.pending.len() == SLOT_COUNT {
+            device.poll(wgpu::Maintain::Wait);
+            self.write_oldest(true)?;
+        }
+        let slot = (0..SLOT_COUNT).find(|&i| self.slots[i].sample.is_none()).unwrap();
+        let source = &simulation.boid_buffers()[simulation.current_buffer()];
+        encoder.copy_buffer_to_buffer(source, 0, &self.slots[slot].buffer, 0, simulation.boids_size());
+        self.slots[slot].sample = Some((simulation.step_count(), sim_time));
+        self.pending.push_back(slot);
+        Ok(())
+    }
+
+    /// Starts mapping the copies, once the encoders copying them were submitted
+    pub fn start_reading(&mut self) {
+        for &slot in &self.pending {
+            let slot = &mut self.slots[slot];
+            if slot.mapping.is_none() {
+                slot.mapping = Some(map_read(&slot.buffer));
+            }
+        }
+    }
+
+    /// Writes the copies that reached the CPU, in order and without waiting for the GPU
+    pub fn write_ready(&mut self, device: &Device) -> anyhow::Result<()> {
+        device.poll(wgpu::Maintain::Poll);
+        while self.write_oldest(false)? {}
+        Ok(())
+    }
+
+    /// Waits for the copies still on their way and writes them, returning the number of samples
+    pub fn stop(mut self, device: &Device) -> anyhow::Result<u32> {
+        self.start_reading();
+        device.poll(wgpu::Maintain::Wait);
+        while self.write_oldest(true)? {}
+        self.writer.flush().with_context(|| format!("could not write to {}", self.path.display()))?;
+        Ok(self.samples)
+    }
+
+    // Writes the oldest copy if it was mapped, or waiting for it, returning whether one was written
+    fn write_oldest(&mut self, wait: bool) -> anyhow::Result<bool> {
+        let slot = match self.pending.front() {
+            Some(&slot) => &mut self.slots[slot],
+            None => return Ok(false),
+        };
+        let mapping = slot.mapping.as_mut().context("the boids were copied but not submitted")?;
+        let result = match (poll_mapping(mapping), wait) {
+            (Poll::Ready(result), _) => result,
+            (Poll::Pending, true) => pollster::block_on(slot.mapping.take().unwrap()),
+            (Poll::Pending, false) => return Ok(false),
+        };
+        result.context("could not read the boids back")?;
+
+        let boids = slot.buffer.slice(..).get_mapped_range().to_vec();
+        slot.buffer.unmap();
+        slot.mapping = None;
+        let (step, sim_time) = slot.sample.take().unwrap();
+        self.pending.pop_front();
+        write_sample(&mut self.writer, self.format, self.dimensions, step, sim_time, &boids)
+            .with_context(|| format!("could not write to {}", self.path.display()))?;
+        self.samples += 1;
+        Ok(true)
+    }
+}
+
+fn write_header(writer: &mut impl Write, format: TrajectoryFormat, dimensions: Dimensions, boid_count: u32) -> std::io::Result<()> {
+    match format {
+        TrajectoryFormat::Csv => {
+            let columns = match dimensions {
+                Dimensions::Two => "x,y,vx,vy",
+                Dimensions::Three => "x,y,z,vx,vy,vz",
+            };
+            writeln!(writer, "step,sim_time,id,{},r,g,b,a", columns)
+        }
+        TrajectoryFormat::Binary => {
+            writer.write_all(BINARY_MAGIC)?;
+            writer.write_all(&(dimension_count(dimensions) as u32).to_le_bytes())?;
+            writer.write_all(&boid_count.to_le_bytes())
+        }
+    }
+}
+
+// Writes the boids of a sample as read back by BoidSimulation::read_boids
+fn write_sample(writer: &mut impl Write, format: TrajectoryFormat, dimensions: Dimensions, step: u32, sim_time: f32, boids: &[u8]) -> anyhow::Result<()> {
+    // x, y[, z], vx, vy[, vz], r, g, b, a of every boid
+    let rows: Vec<Vec<f32>> = match dimensions {
+        Dimensions::Two => decode_boids::<Boid>(boids)?.iter()
+            .map(|boid| [&boid.position()[..], &boid.speed()[..], &boid.color()[..]].concat())
+            .collect(),
+        Dimensions::Three => decode_boids::<Boid3>(boids)?.iter()
+            .map(|boid| [&boid.position()[..], &boid.speed()[..], &boid.color()[..]].concat())
+            .collect(),
+    };
+
+    match format {
+        TrajectoryFormat::Csv => {
+            for (id, row) in rows.iter().enumerate() {
+                write!(writer, "{},{},{}", step, sim_time, id)?;
+                for value in row {
+                    write!(writer, ",{}", value)?;
+                }
+                writeln!(writer)?;
+            }
+        }
+        TrajectoryFormat::Binary => {
+            writer.write_all(&step.to_le_bytes())?;
+            writer.write_all(&sim_time.to_le_bytes())?;
+            for id in 0..rows.len() as u32 {
+                writer.write_all(&id.to_le_bytes())?;
+            }
+            for column in 0..rows.first().map_or(0, Vec::len) {
+                for row in &rows {
+                    writer.write_all(&row[column].to_le_bytes())?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dimension_count(dimensions: Dimensions) -> usize {
+    match dimensions {
+        Dimensions::Two => 2,
+        Dimensions::Three => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use super::*;
+
+    // Splits the next n bytes off the data
+    fn take<'a>(data: &mut &'a [u8], n: usize) -> &'a [u8] {
+        let (head, tail) = data.split_at(n);
+        *data = tail;
+        head
+    }
+
+    fn u32_at(data: &mut &[u8]) -> u32 {
+        u32::from_le_bytes(take(data, 4).try_into().unwrap())
+    }
+
+    fn f32_at(data: &mut &[u8]) -> f32 {
+        f32::from_le_bytes(take(data, 4).try_into().unwrap())
+    }
+
+    #[test]
+    fn binary_samples() {
+        let boids = [
+            Boid::new([1.0, 2.0], [0.5, -0.5], [0.1, 0.2, 0.3, 1.0], 0),
+            Boid::new([-3.0, 4.0], [0.0, 1.5], [0.4, 0.5, 0.6, 0.5], 1),
+        ];
+        let mut file = Vec::new();
+        write_header(&mut file, TrajectoryFormat::Binary, Dimensions::Two, 2).unwrap();
+        for (step, sim_time) in [(10, 0.25), (20, 0.5)] {
+            write_sample(&mut file, TrajectoryFormat::Binary, Dimensions::Two, step, sim_time, bytemuck::cast_slice(&boids)).unwrap();
+        }
+
+        let mut data = &file[..];
+        assert_eq!(take(&mut data, 8), BINARY_MAGIC);
+        assert_eq!(u32_at(&mut data), 2);
+        assert_eq!(u32_at(&mut data), 2);
+        for (step, sim_time) in [(10, 0.25), (20, 0.5)] {
+            assert_eq!(u32_at(&mut data), step);
+            assert_eq!(f32_at(&mut data), sim_time);
+            assert_eq!([u32_at(&mut data), u32_at(&mut data)], [0, 1]);
+            // x, y, vx, vy, r, g, b, a columns
+            let columns = [
+                [1.0, -3.0], [2.0, 4.0], [0.5, 0.0], [-0.5, 1.5],
+                [0.1, 0.4], [0.2, 0.5], [0.3, 0.6], [1.0, 0.5],
+            ];
+            for column in columns {
+                assert_eq!([f32_at(&mut data), f32_at(&mut data)], column);
+            }
+        }
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn csv_samples() {
+        let boids = [Boid3::new([1.0, 2.0, 3.0], [0.5, 0.0, -0.5], [0.1, 0.2, 0.3, 1.0], 0)];
+        let mut file = Vec::new();
+        write_header(&mut file, TrajectoryFormat::Csv, Dimensions::Three, 1).unwrap();
+        write_sample(&mut file, TrajectoryFormat::Csv, Dimensions::Three, 10, 0.25, bytemuck::cast_slice(&boids)).unwrap();
+        assert_eq!(
+            String::from_utf8(file).unwrap(),
+            "step,sim_time,id,x,y,z,vx,vy,vz,r,g,b,a\n10,0.25,0,1,2,3,0.5,0,-0.5,0.1,0.2,0.3,1\n",
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use bytemuck::{Pod, Zeroable};
+
+// Must match ALL_SPECIES in the compute shaders
+const ALL_SPECIES: u32 = u32::MAX;
+
+/// A list of waypoints the boids steer towards one after the other.
+/// A boid moves on to the next waypoint once it is within the arrival radius of the current one
+/// and either goes back to the first one or settles on the last one when the path is over.
+/// A single waypoint makes an attractor point
+#[derive(Clone, Debug)]
+pub struct WaypointPath {
+    /// Points of the path in world coordinates, in the order they are visited
+    pub waypoints: Vec<[f32; 2]>,
+    /// Distance at which a waypoint is reached
+    pub arrival_radius: f32,
+    /// Whether the boids go back to the first waypoint after the last one
+    pub looped: bool,
+    /// Species following the path, None for every boid
+    pub species: Option<u32>,
+}
+
+impl WaypointPath {
+    /// A path followed once by the whole flock
+    pub fn new(waypoints: Vec<[f32; 2]>, arrival_radius: f32) -> Self {
+        Self { waypoints, arrival_radius, looped: false, species: None }
+    }
+
+    /// A single point the boids gather around
+    pub fn attractor(point: [f32; 2], arrival_radius: f32) -> Self {
+        Self::new(vec![point], arrival_radius)
+    }
+
+    /// Makes the boids go around the path endlessly
+    pub fn looped(mut self) -> Self {
+        self.looped = true;
+        self
+    }
+
+    /// Restricts the path to the boids of a species, by default the whole flock follows it
+    pub fn for_species(mut self, species: u32) -> Self {
+        self.species = Some(species);
+        self
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub(crate) struct GpuPath {
+    start: u32,
+    count: u32,
+    species: u32,
+    looped: u32,
+    arrival_radius: f32,
+}
+
+/// Number of paths that actually get uploaded
+pub(crate) fn path_count(paths: &[WaypointPath]) -> u32 {
+    paths.iter().filter(|p| !p.waypoints.is_empty()).count() as u32
+}
+
+/// Flattens the paths into the path headers and the waypoints they index into.
+/// Empty paths are dropped and both lists hold at least one element as buffers can't be empty
+pub(crate) fn build_path_buffers(paths: &[WaypointPath]) -> (Vec<GpuPath>, Vec<[f32; 2]>) {
+    let mut headers = vec![];
+    let mut waypoints = vec![];
+    for path in paths.iter().filter(|p| !p.waypoints.is_empty()) {
+        headers.push(GpuPath {
+            start: waypoints.len() as u32,
+            count: path.waypoints.len() as u32,
+            species: path.species.unwrap_or(ALL_SPECIES),
+            looped: path.looped as u32,
+            arrival_radius: path.arrival_radius,
+        });
+        waypoints.extend_from_slice(&path.waypoints);
+    }
+    if headers.is_empty() {
+        headers.push(GpuPath::zeroed());
+    }
+    if waypoints.is_empty() {
+        waypoints.push([0.0, 0.0]);
+    }
+    (headers, waypoints)
+}
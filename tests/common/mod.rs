@@ -0,0 +1,23 @@
+// Helpers shared by the integration tests that need a GPU adapter. Those tests are ignored by
+// default and run with `cargo test -- --ignored`, failing when no adapter can be opened
+
+// A headless device, preferably on the software adapter so that the results don't depend on the GPU
+pub fn device() -> (wgpu::Device, wgpu::Queue) {
+    let instance = wgpu::Instance::new(wgpu::Backends::all());
+    let adapter = [true, false].iter().find_map(|&force_fallback_adapter| {
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: None,
+            force_fallback_adapter,
+        }))
+    }).expect("no GPU adapter available, these tests need one");
+    let device = pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+            label: None,
+        },
+        None,
+    ));
+    device.unwrap_or_else(|e| panic!("could not open {} : {:?}", adapter.get_info().name, e))
+}
@@ -0,0 +1,275 @@
+// Renders fixed flocks offscreen with the default camera and compares them with the reference
+// images of tests/golden. A missing reference fails the test, UPDATE_GOLDEN=1 writing all of
+// them instead, to be reviewed and committed after an intended change of the rendering.
+// The actual and diff images of a failing test are written to the target directory.
+// The references were rendered on Mesa's llvmpipe, the software adapter common::device prefers.
+
+mod common;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use boids_web::{
+    Boid, Boid3, BoidRenderer, BoidShape, BoidSimulation, ColorMode, Colormap, DebugView, Dimensions,
+    HeatmapMode, RenderParams, SimulationParams,
+};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+// Perceptual difference above which two pixels differ, from 0 to 1
+const PIXEL_THRESHOLD: f32 = 0.1;
+// Share of the pixels allowed to differ, rasterization rules varying a little between adapters
+const MAX_DIFF_RATIO: f32 = 0.005;
+
+fn simulation_params(dimensions: Dimensions) -> SimulationParams {
+    SimulationParams {
+        dimensions,
+        species_count: 3,
+        ..SimulationParams::default()
+    }
+}
+
+// Only the boids, without multisampling which resolves differently between adapters
+fn render_params() -> RenderParams {
+    RenderParams {
+        color_mode: ColorMode::Stored,
+        colormap: Colormap::Viridis,
+        max_density: 20.0,
+        trails: false,
+        trail_length: 64,
+        trail_decay: 2.0,
+        msaa_samples: 1,
+        alpha_blending: true,
+        boid_alpha: 1.0,
+        shapes: vec![BoidShape::Arrow],
+        boid_size: 0.6,
+        heatmap: HeatmapMode::Off,
+        heatmap_resolution: 128,
+        heatmap_extent: 40.0,
+        heatmap_max: 2.0,
+        heatmap_opacity: 0.8,
+        debug: DebugView::Off,
+        debug_selection: vec![],
+        debug_vector_scale: 0.1,
+        background: false,
+        world_half_size: 30.0,
+    }
+}
+
+// Position, speed, color and species of a boid in either dimension
+type BoidData = ([f32; 3], [f32; 3], [f32; 4], u32);
+
+// A 5x5 grid of boids heading in every direction, colored and assigned to species by index
+fn snapshot(spacing: f32) -> Vec<BoidData> {
+    (0..25).map(|i| {
+        let (x, y) = ((i % 5) as f32 - 2.0, (i / 5) as f32 - 2.0);
+        let angle = i as f32 * std::f32::consts::TAU / 25.0;
+        let t = i as f32 / 24.0;
+        (
+            [x * spacing, y * spacing, (x - y) * spacing * 0.5],
+            [angle.cos() * (0.2 + 0.8 * t), angle.sin() * (0.2 + 0.8 * t), 0.3],
+            [t, 1.0 - t, 0.5, 1.0],
+            i % 3,
+        )
+    }).collect()
+}
+
+fn boid_bytes(dimensions: Dimensions) -> Vec<u8> {
+    match dimensions {
+        Dimensions::Two => {
+            let boids: Vec<Boid> = snapshot(1.2).into_iter()
+                .map(|(p, s, c, species)| Boid::new([p[0], p[1]], [s[0], s[1]], c, species))
+                .collect();
+            bytemuck::cast_slice(&boids).to_vec()
+        }
+        Dimensions::Three => {
+            let boids: Vec<Boid3> = snapshot(6.0).into_iter()
+                .map(|(p, s, c, species)| Boid3::new(p, s, c, species))
+                .collect();
+            bytemuck::cast_slice(&boids).to_vec()
+        }
+    }
+}
+
+// RGBA pixels of the snapshot drawn with the given parameters
+fn render(device: &wgpu::Device, queue: &wgpu::Queue, dimensions: Dimensions, render_params: RenderParams) -> Vec<u8> {
+    let boids = boid_bytes(dimensions);
+    let simulation = BoidSimulation::new(device, queue, simulation_params(dimensions), 25, 1.0, 0);
+    simulation.write_boids(queue, &boids).unwrap();
+    let size = winit::dpi::PhysicalSize::new(WIDTH, HEIGHT);
+    let renderer = BoidRenderer::new(device, queue, FORMAT, size, &simulation, render_params);
+
+    let extent = wgpu::Extent3d { width: WIDTH, height: HEIGHT, depth_or_array_layers: 1 };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Golden Texture"),
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let bytes_per_row = WIDTH * 4;
+    assert_eq!(bytes_per_row % wgpu::COPY_BYTES_PER_ROW_ALIGNMENT, 0);
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Golden readback buffer"),
+        size: (bytes_per_row * HEIGHT) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    renderer.render(&mut encoder, &view, &simulation).unwrap();
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &readback_buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        extent,
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = readback_buffer.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    pollster::block_on(mapping).unwrap();
+    let pixels = slice.get_mapped_range().to_vec();
+    readback_buffer.unmap();
+    pixels
+}
+
+fn write_png(path: &Path, pixels: &[u8]) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path).unwrap()), WIDTH, HEIGHT);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(pixels).unwrap();
+}
+
+fn read_png(path: &Path) -> (u32, u32, Vec<u8>) {
+    let mut decoder = png::Decoder::new(File::open(path).unwrap());
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgba, "{} is not an RGBA image", path.display());
+    pixels.truncate(info.buffer_size());
+    (info.width, info.height, pixels)
+}
+
+fn yiq(pixel: &[u8]) -> [f32; 3] {
+    // Blending over white the way an image viewer would
+    let alpha = pixel[3] as f32 / 255.0;
+    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|c| 255.0 + (c as f32 - 255.0) * alpha);
+    [
+        r * 0.298_895_3 + g * 0.586_622_5 + b * 0.114_482_2,
+        r * 0.595_978 - g * 0.274_176_1 - b * 0.321_801_9,
+        r * 0.211_470_2 - g * 0.522_617_1 + b * 0.311_146_9,
+    ]
+}
+
+// Squared YIQ distance normalized to [0, 1], close to how different the colors look
+fn perceptual_delta(a: &[u8], b: &[u8]) -> f32 {
+    let (a, b) = (yiq(a), yiq(b));
+    let d = [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+    (0.5053 * d[0] * d[0] + 0.299 * d[1] * d[1] + 0.1957 * d[2] * d[2]) / 35215.0
+}
+
+fn check(name: &str, dimensions: Dimensions, render_params: RenderParams) {
+    let (device, queue) = common::device();
+    let actual = render(&device, &queue, dimensions, render_params);
+
+    let reference = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        write_png(&reference, &actual);
+        eprintln!("Wrote the reference image {}", reference.display());
+        return;
+    }
+    assert!(
+        reference.exists(),
+        "{} is missing, run the test with UPDATE_GOLDEN=1 to write it",
+        reference.display(),
+    );
+
+    let (width, height, expected) = read_png(&reference);
+    assert_eq!((width, height), (WIDTH, HEIGHT), "{} has the wrong size", reference.display());
+    let mut diff = Vec::with_capacity(expected.len());
+    let mut differing = 0;
+    for (a, e) in actual.chunks(4).zip(expected.chunks(4)) {
+        if perceptual_delta(a, e) > PIXEL_THRESHOLD * PIXEL_THRESHOLD {
+            differing += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            // The expected image faded to light gray
+            let gray = (255.0 + (yiq(e)[0] - 255.0) * 0.1) as u8;
+            diff.extend_from_slice(&[gray, gray, gray, 255]);
+        }
+    }
+
+    let ratio = differing as f32 / (WIDTH * HEIGHT) as f32;
+    if ratio > MAX_DIFF_RATIO {
+        let output = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        write_png(&output.join(format!("{}.actual.png", name)), &actual);
+        write_png(&output.join(format!("{}.diff.png", name)), &diff);
+        panic!(
+            "{} differs from {} on {:.2}% of the pixels, see the images in {}",
+            name, reference.display(), ratio * 100.0, output.display(),
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn arrows_2d() {
+    check("arrows_2d", Dimensions::Two, render_params());
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn shapes_2d() {
+    check("shapes_2d", Dimensions::Two, RenderParams {
+        color_mode: ColorMode::Species,
+        shapes: vec![
+            BoidShape::Triangle,
+            BoidShape::Circle,
+            BoidShape::Polygon(vec![[0.0, 0.5], [-0.4, 0.0], [0.0, -0.5], [0.4, 0.0]]),
+        ],
+        ..render_params()
+    });
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn speed_colormap_2d() {
+    check("speed_colormap_2d", Dimensions::Two, RenderParams {
+        color_mode: ColorMode::Speed,
+        colormap: Colormap::Magma,
+        ..render_params()
+    });
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn translucent_background_2d() {
+    check("translucent_background_2d", Dimensions::Two, RenderParams {
+        boid_alpha: 0.5,
+        background: true,
+        ..render_params()
+    });
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn darts_3d() {
+    check("darts_3d", Dimensions::Three, RenderParams {
+        boid_size: 3.0,
+        ..render_params()
+    });
+}
@@ -0,0 +1,403 @@
+// Runs the compute shaders and a CPU port of them from the same seeded flock and compares
+// the resulting positions and velocities. Shader edits have to be mirrored in the port.
+
+mod common;
+
+use bytemuck::Zeroable;
+use rand::distributions::{Distribution, Uniform};
+use rand::SeedableRng;
+use boids_web::application::MAX_NEIGHBOURS;
+use boids_web::{Boid, Boid3, BoidSimulation, Dimensions, FlowField, Neighbourhood, SimulationParams, WaypointPath};
+
+const BOID_COUNT: u32 = 100;
+const STEPS: u32 = 20;
+const DELTA_TIME: f32 = 0.05;
+// Relative error allowed between the GPU and the CPU, the flocks drifting apart with
+// the rounding differences of the two implementations
+const TOLERANCE: f32 = 2e-3;
+
+// Steering rules of the compute shaders
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Rules {
+    // compute2.wgsl and compute3.wgsl, neighbours weighted by their color
+    Colored,
+    // compute.wgsl, every neighbour counting the same
+    Plain,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct CpuBoid<const D: usize> {
+    position: [f32; D],
+    speed: [f32; D],
+    color: [f32; 3],
+    species: u32,
+}
+
+fn add<const D: usize>(a: [f32; D], b: [f32; D]) -> [f32; D] {
+    let mut r = a;
+    for i in 0..D {
+        r[i] += b[i];
+    }
+    r
+}
+
+fn sub<const D: usize>(a: [f32; D], b: [f32; D]) -> [f32; D] {
+    let mut r = a;
+    for i in 0..D {
+        r[i] -= b[i];
+    }
+    r
+}
+
+fn scale<const D: usize>(a: [f32; D], s: f32) -> [f32; D] {
+    a.map(|x| x * s)
+}
+
+fn length<const D: usize>(a: [f32; D]) -> f32 {
+    a.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+fn distance<const D: usize>(a: [f32; D], b: [f32; D]) -> f32 {
+    length(sub(a, b))
+}
+
+fn normalize<const D: usize>(a: [f32; D]) -> [f32; D] {
+    scale(a, 1.0 / length(a))
+}
+
+// Points in the z = 0 plane in 3D
+fn extend<const D: usize>(point: [f32; 2]) -> [f32; D] {
+    let mut r = [0.0; D];
+    r[0] = point[0];
+    r[1] = point[1];
+    r
+}
+
+fn zero<const D: usize>() -> [f32; D] {
+    [0.0; D]
+}
+
+// sampleFlow in the compute shaders
+fn sample_flow(flow: &FlowField, pos: [f32; 2]) -> [f32; 2] {
+    let cell = [(pos[0] - flow.origin[0]) / flow.cell_size, (pos[1] - flow.origin[1]) / flow.cell_size];
+    if cell[0] < 0.0 || cell[1] < 0.0 || cell[0] > (flow.width - 1) as f32 || cell[1] > (flow.height - 1) as f32 {
+        return [0.0, 0.0];
+    }
+    let (x0, y0) = (cell[0] as u32, cell[1] as u32);
+    let x1 = (x0 + 1).min(flow.width - 1);
+    let y1 = (y0 + 1).min(flow.height - 1);
+    let t = [cell[0] - cell[0].floor(), cell[1] - cell[1].floor()];
+
+    let at = |x: u32, y: u32| flow.cells[(y * flow.width + x) as usize];
+    let (c00, c10, c01, c11) = (at(x0, y0), at(x1, y0), at(x0, y1), at(x1, y1));
+    let bottom = add(c00, scale(sub(c10, c00), t[0]));
+    let top = add(c01, scale(sub(c11, c01), t[0]));
+    add(bottom, scale(sub(top, bottom), t[1]))
+}
+
+// The step entry point of the compute shaders, with the mouse released
+fn cpu_step<const D: usize>(boids: &[CpuBoid<D>], waypoints: &mut [u32], params: &SimulationParams, rules: Rules, delta_time: f32) -> Vec<CpuBoid<D>> {
+    let min_speed = params.min_speed.max(0.0);
+    let max_speed = params.max_speed.max(min_speed);
+    let inertia = params.inertia.max(f32::EPSILON);
+    let max_steering_force = params.max_steering_force.max(0.0);
+    let (separation_k, alignement_k, cohesion_k) = (
+        params.separation_k.min(MAX_NEIGHBOURS),
+        params.alignement_k.min(MAX_NEIGHBOURS),
+        params.cohesion_k.min(MAX_NEIGHBOURS),
+    );
+    let topological = params.neighbourhood == Neighbourhood::Topological;
+    let paths: Vec<&WaypointPath> = match params.follow_paths {
+        true => params.paths.iter().filter(|path| !path.waypoints.is_empty()).collect(),
+        false => vec![],
+    };
+
+    let mut out = boids.to_vec();
+    for index in 0..boids.len() {
+        let v_pos = boids[index].position;
+        let mut v_vel = boids[index].speed;
+        let v_color = boids[index].color;
+
+        // The k nearest boids sorted by distance
+        let k = separation_k.max(alignement_k).max(cohesion_k).min(MAX_NEIGHBOURS) as usize;
+        let mut nearest: Vec<(f32, usize)> = vec![];
+        if topological && k > 0 {
+            for (i, other) in boids.iter().enumerate() {
+                if i != index {
+                    nearest.push((distance(other.position, v_pos), i));
+                }
+            }
+            nearest.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            nearest.truncate(k);
+        }
+        let neighbours: Vec<usize> = match topological {
+            true => nearest.iter().map(|&(_, i)| i).collect(),
+            false => (0..boids.len()).collect(),
+        };
+
+        let (mut sep_sum, mut sep_count) = (zero::<D>(), 0.0);
+        let (mut ali_sum, mut ali_count) = (zero::<D>(), 0.0);
+        let (mut coh_sum, mut coh_count) = (zero::<D>(), 0.0);
+        for (n, &i) in neighbours.iter().enumerate() {
+            if i == index {
+                continue;
+            }
+            let other = &boids[i];
+            let dist = distance(other.position, v_pos);
+            let (in_separation, in_alignement, in_cohesion) = match topological {
+                true => ((n as u32) < separation_k, (n as u32) < alignement_k, (n as u32) < cohesion_k),
+                false => (dist < params.separation_reach, dist < params.alignement_reach, dist < params.cohesion_reach),
+            };
+            match rules {
+                Rules::Colored => {
+                    let color_m = (1. - distance(other.color, v_color) / 1.732_050_8) * params.color_mult;
+                    if in_separation {
+                        sep_sum = add(sep_sum, scale(normalize(sub(v_pos, other.position)), 1.0 / (dist * dist + 0.2)));
+                        sep_count += color_m;
+                    }
+                    if in_alignement {
+                        ali_sum = add(ali_sum, scale(other.speed.map(|x| x + 0.2), color_m / (dist + 0.2)));
+                        ali_count += color_m / dist;
+                    }
+                    if in_cohesion {
+                        coh_sum = add(coh_sum, scale(other.position, color_m));
+                        coh_count += color_m;
+                    }
+                }
+                Rules::Plain => {
+                    if in_separation {
+                        sep_sum = add(sep_sum, scale(normalize(sub(v_pos, other.position)), 1.0 / (dist * dist)));
+                        sep_count += 1.0;
+                    }
+                    if in_alignement {
+                        ali_sum = add(ali_sum, other.speed);
+                        ali_count += 1.0;
+                    }
+                    if in_cohesion {
+                        coh_sum = add(coh_sum, other.position);
+                        coh_count += 1.0;
+                    }
+                }
+            }
+        }
+
+        let mut steering = zero::<D>();
+        if sep_count > 0.0 {
+            steering = add(steering, scale(sep_sum, params.separation_scale));
+        }
+        if ali_count > 0.0 {
+            // compute.wgsl doesn't average the velocities of the neighbours
+            let ali_mean = match rules {
+                Rules::Colored => scale(ali_sum, 1.0 / ali_count),
+                Rules::Plain => ali_sum,
+            };
+            steering = add(steering, scale(ali_mean, params.alignement_scale));
+        }
+        if coh_count > 0.0 {
+            let center_of_grav = scale(coh_sum, 1.0 / coh_count);
+            steering = add(steering, scale(sub(center_of_grav, v_pos), params.cohesion_scale));
+        }
+        let distance_center = length(v_pos);
+        let center_pull = match rules {
+            Rules::Colored => scale(normalize(v_pos), -params.center_attraction / (1.0 - (-distance_center + 20.0).exp2())),
+            Rules::Plain => scale(v_pos, -distance_center * params.center_attraction),
+        };
+        steering = add(steering, center_pull);
+
+        // The first path meant for the species of the boid
+        let species = boids[index].species;
+        if let Some(path) = paths.iter().find(|path| path.species.unwrap_or(species) == species) {
+            let count = path.waypoints.len() as u32;
+            let mut waypoint = waypoints[index].min(count - 1);
+            if distance(extend(path.waypoints[waypoint as usize]), v_pos) < path.arrival_radius {
+                if waypoint + 1 < count {
+                    waypoint += 1;
+                } else if path.looped {
+                    waypoint = 0;
+                }
+                waypoints[index] = waypoint;
+            }
+            let to_goal = sub(extend(path.waypoints[waypoint as usize]), v_pos);
+            let goal_dist = length(to_goal);
+            if goal_dist > 0.00001 {
+                let mut desired_speed = max_speed;
+                if waypoint + 1 == count && !path.looped {
+                    desired_speed *= (goal_dist / path.arrival_radius).min(1.0);
+                }
+                steering = add(steering, scale(sub(scale(to_goal, desired_speed / goal_dist), v_vel), params.goal_scale));
+            }
+        }
+
+        let force = length(steering);
+        if force > max_steering_force {
+            steering = scale(steering, max_steering_force / force);
+        }
+        let drift = extend(scale(sample_flow(&params.flow_field, [v_pos[0], v_pos[1]]), params.flow_scale));
+        v_vel = add(v_vel, scale(add(steering, drift), delta_time / inertia));
+
+        let speed = length(v_vel);
+        if speed > 0.00001 {
+            v_vel = scale(v_vel, speed.clamp(min_speed, max_speed) / speed);
+        } else {
+            v_vel = zero();
+            v_vel[1] = min_speed;
+        }
+
+        out[index].position = add(v_pos, scale(v_vel, delta_time));
+        out[index].speed = v_vel;
+    }
+    out
+}
+
+fn params(dimensions: Dimensions, neighbourhood: Neighbourhood, follow_paths: bool) -> SimulationParams {
+    SimulationParams {
+        dimensions,
+        neighbourhood,
+        flow_field: FlowField::new([-30.0, -30.0], 1.0, 61, 61).vortex([0.0, 0.0], 15.0),
+        paths: vec![
+            WaypointPath::new(vec![[-5.0, -5.0], [5.0, -5.0], [5.0, 5.0], [-5.0, 5.0]], 4.0)
+                .looped()
+                .for_species(0),
+            WaypointPath::attractor([0.0, 0.0], 5.0).for_species(1),
+        ],
+        follow_paths,
+        ..SimulationParams::default()
+    }
+}
+
+// The same seeded flock for both implementations
+fn initial_flock<const D: usize>() -> Vec<CpuBoid<D>> {
+    let mut rng = rand_pcg::Pcg64::seed_from_u64(7);
+    let position = Uniform::from(-10.0..10.0);
+    let speed = Uniform::from(-1.0..1.0);
+    let color = Uniform::from(0.0..1.0);
+    (0..BOID_COUNT).map(|i| CpuBoid {
+        position: [(); D].map(|_| position.sample(&mut rng)),
+        speed: [(); D].map(|_| speed.sample(&mut rng)),
+        color: [(); 3].map(|_| color.sample(&mut rng)),
+        species: i % 2,
+    }).collect()
+}
+
+fn run_gpu(device: &wgpu::Device, queue: &wgpu::Queue, params: SimulationParams, rules: Rules, boids: &[u8]) -> Vec<u8> {
+    let mut simulation = match rules {
+        Rules::Colored => BoidSimulation::new(device, queue, params, BOID_COUNT, 1.0, 0),
+        Rules::Plain => BoidSimulation::with_shader(device, queue, params, BOID_COUNT, 1.0, 0, include_str!("../src/compute.wgsl")),
+    };
+    simulation.write_boids(queue, boids).unwrap();
+    for _ in 0..STEPS {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        simulation.step(queue, &mut encoder, DELTA_TIME);
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+    simulation.read_boids(device, queue).unwrap()
+}
+
+fn run_cpu<const D: usize>(params: &SimulationParams, rules: Rules, mut boids: Vec<CpuBoid<D>>) -> Vec<CpuBoid<D>> {
+    let mut waypoints = vec![0; boids.len()];
+    for _ in 0..STEPS {
+        boids = cpu_step(&boids, &mut waypoints, params, rules, DELTA_TIME);
+    }
+    boids
+}
+
+fn assert_close<const D: usize>(what: &str, index: usize, gpu: [f32; D], cpu: [f32; D]) {
+    for i in 0..D {
+        let error = (gpu[i] - cpu[i]).abs();
+        assert!(
+            error <= TOLERANCE * cpu[i].abs().max(1.0),
+            "{} of boid {} differs : GPU {:?}, CPU {:?}",
+            what, index, gpu, cpu,
+        );
+    }
+}
+
+fn check_2d(rules: Rules, neighbourhood: Neighbourhood, follow_paths: bool) {
+    let (device, queue) = common::device();
+    let params = params(Dimensions::Two, neighbourhood, follow_paths);
+    let flock = initial_flock::<2>();
+    let boids: Vec<Boid> = flock.iter()
+        .map(|b| Boid::new(b.position, b.speed, [b.color[0], b.color[1], b.color[2], 1.0], b.species))
+        .collect();
+
+    let bytes = run_gpu(&device, &queue, params.clone(), rules, bytemuck::cast_slice(&boids));
+    let mut gpu = vec![Boid::zeroed(); boids.len()];
+    bytemuck::cast_slice_mut(&mut gpu).copy_from_slice(&bytes);
+    let cpu = run_cpu(&params, rules, flock);
+    for (i, (gpu, cpu)) in gpu.iter().zip(&cpu).enumerate() {
+        assert_close("position", i, gpu.position(), cpu.position);
+        assert_close("speed", i, gpu.speed(), cpu.speed);
+    }
+}
+
+fn check_3d(neighbourhood: Neighbourhood, follow_paths: bool) {
+    let (device, queue) = common::device();
+    let params = params(Dimensions::Three, neighbourhood, follow_paths);
+    let flock = initial_flock::<3>();
+    let boids: Vec<Boid3> = flock.iter()
+        .map(|b| Boid3::new(b.position, b.speed, [b.color[0], b.color[1], b.color[2], 1.0], b.species))
+        .collect();
+
+    let bytes = run_gpu(&device, &queue, params.clone(), Rules::Colored, bytemuck::cast_slice(&boids));
+    let mut gpu = vec![Boid3::zeroed(); boids.len()];
+    bytemuck::cast_slice_mut(&mut gpu).copy_from_slice(&bytes);
+    let cpu = run_cpu(&params, Rules::Colored, flock);
+    for (i, (gpu, cpu)) in gpu.iter().zip(&cpu).enumerate() {
+        assert_close("position", i, gpu.position(), cpu.position);
+        assert_close("speed", i, gpu.speed(), cpu.speed);
+    }
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn metric_2d() {
+    check_2d(Rules::Colored, Neighbourhood::Metric, false);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn topological_2d() {
+    check_2d(Rules::Colored, Neighbourhood::Topological, false);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn paths_2d() {
+    check_2d(Rules::Colored, Neighbourhood::Metric, true);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn metric_3d() {
+    check_3d(Neighbourhood::Metric, false);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn topological_3d() {
+    check_3d(Neighbourhood::Topological, false);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn paths_3d() {
+    check_3d(Neighbourhood::Metric, true);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn plain_metric_2d() {
+    check_2d(Rules::Plain, Neighbourhood::Metric, false);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn plain_topological_2d() {
+    check_2d(Rules::Plain, Neighbourhood::Topological, false);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn plain_paths_2d() {
+    check_2d(Rules::Plain, Neighbourhood::Metric, true);
+}
@@ -0,0 +1,59 @@
+// Recreates simulations from a snapshot of their boids, the way ApplicationState::recover does
+// on the new device once the device was lost. The simulations are recreated on the same device,
+// the GL backend of the software adapter not supporting a second device in the same process
+
+mod common;
+
+use boids_web::{BoidSimulation, Dimensions, SimulationParams};
+
+const BOID_COUNT: u32 = 256;
+const STEPS: u32 = 5;
+const DELTA_TIME: f32 = 1.0 / 60.0;
+
+fn run(device: &wgpu::Device, queue: &wgpu::Queue, simulation: &mut BoidSimulation) -> Vec<u8> {
+    for _ in 0..STEPS {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        simulation.step(queue, &mut encoder, DELTA_TIME);
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+    simulation.read_boids(device, queue).unwrap()
+}
+
+fn check(dimensions: Dimensions) {
+    let (device, queue) = common::device();
+    let params = SimulationParams { dimensions, ..SimulationParams::default() };
+    let mut simulation = BoidSimulation::new(&device, &queue, params, BOID_COUNT, 1.0, 0);
+    let snapshot = run(&device, &queue, &mut simulation);
+
+    let mut recreated = simulation.recreate(&device, &queue, Some(&snapshot)).unwrap();
+    assert_eq!(recreated.read_boids(&device, &queue).unwrap(), snapshot);
+    assert_eq!(recreated.step_count(), simulation.step_count());
+    assert_eq!(recreated.boid_count(), BOID_COUNT);
+
+    // Both flocks carry on the same way from the snapshot
+    let expected = run(&device, &queue, &mut simulation);
+    drop(simulation);
+    assert_eq!(run(&device, &queue, &mut recreated), expected);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn recover_2d() {
+    check(Dimensions::Two);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn recover_3d() {
+    check(Dimensions::Three);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn recover_without_snapshot() {
+    let (device, queue) = common::device();
+    let simulation = BoidSimulation::new(&device, &queue, SimulationParams::default(), BOID_COUNT, 1.0, 0);
+    let recreated = simulation.recreate(&device, &queue, None).unwrap();
+    // A new random flock
+    assert_ne!(recreated.read_boids(&device, &queue).unwrap(), simulation.read_boids(&device, &queue).unwrap());
+}